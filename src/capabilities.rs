@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// What we know about a gvim binary from its `--version` output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capabilities {
+    pub version: String,
+    pub clientserver: bool,
+}
+
+impl Capabilities {
+    // Neovim lists no features, so there is no "-clientserver" to find, but it has none of the
+    // --remote-* options either.
+    pub fn from_version_output(output: &str) -> Self {
+        let version = output.lines().next().unwrap_or_default().trim().to_string();
+
+        Capabilities {
+            clientserver: !version.starts_with("NVIM") && !output.contains("-clientserver"),
+            version,
+        }
+    }
+
+    // What the name of the editor tells already, without running it: nvim-qt never has a
+    // client-server.
+    pub fn of_editor(editor: &Path) -> Option<Self> {
+        let name = editor.file_stem()?.to_string_lossy().to_lowercase();
+
+        if name != "nvim-qt" {
+            return None;
+        }
+
+        Some(Capabilities {
+            version: name,
+            clientserver: false,
+        })
+    }
+}
+
+// Running `gvim --version` costs more than everything else gvi does, so the result is kept on
+// disk until the binary changes (i.e. its path or modification time differs).
+pub struct CapabilityCache {
+    pub file: PathBuf,
+}
+
+impl CapabilityCache {
+    pub fn default_location(env: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        #[cfg(target_os = "windows")]
+        let dir = env("LOCALAPPDATA").map(PathBuf::from);
+
+        #[cfg(not(target_os = "windows"))]
+        let dir = env("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env("HOME").map(|home| Path::new(&home).join(".cache")));
+
+        dir.map(|dir| CapabilityCache {
+            file: dir.join("gvi").join("capabilities"),
+        })
+    }
+
+    pub fn load(&self, editor: &Path, mtime: SystemTime) -> Option<Capabilities> {
+        let content = std::fs::read_to_string(&self.file).ok()?;
+        let mut fields = std::collections::HashMap::new();
+
+        for line in content.lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                fields.insert(key, value);
+            }
+        }
+
+        if fields.get("editor") != Some(&editor.to_string_lossy().as_ref())
+            || fields.get("mtime") != Some(&stamp(mtime).as_str())
+        {
+            return None;
+        }
+
+        Some(Capabilities {
+            version: fields.get("version")?.to_string(),
+            clientserver: fields.get("clientserver")?.parse().ok()?,
+        })
+    }
+
+    pub fn store(
+        &self,
+        editor: &Path,
+        mtime: SystemTime,
+        capabilities: &Capabilities,
+    ) -> std::io::Result<()> {
+        if let Some(dir) = self.file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        std::fs::write(
+            &self.file,
+            format!(
+                "editor={}\nmtime={}\nversion={}\nclientserver={}\n",
+                editor.to_string_lossy(),
+                stamp(mtime),
+                capabilities.version,
+                capabilities.clientserver
+            ),
+        )
+    }
+}
+
+fn stamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "{}.{:09}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos()
+    )
+}
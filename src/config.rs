@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Settings read from the config file, which is written in a small subset of TOML:
+//
+//     # comment
+//     sort-by = "mtime"
+//     strict = true
+//
+//     [routes]
+//     md = "NOTES"
+//     rs = "CODE"
+//
+//     [presets]
+//     review = "vsplit 2"
+//
+//     [profiles.work]
+//     server-name = "WORK"
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    // Top level keys are long option names, taken as defaults for the command line.
+    pub options: Vec<(String, String)>,
+    // File extension (without the dot) to the name of the server its files are opened in.
+    pub routes: HashMap<String, String>,
+    // Layouts for --preset, by name.
+    pub presets: HashMap<String, Layout>,
+    // Settings like the top level ones, taking their place for --profile-name.
+    pub profiles: HashMap<String, Vec<(String, String)>>,
+}
+
+// How --preset lays out the files: so many to a tab page (all of them when unset), each in a
+// window split off from the one before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub split: Split,
+    pub per_tab: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Split {
+    Horizontal,
+    Vertical,
+}
+
+impl Layout {
+    // "tab" for a tab page per file, or "split" or "vsplit", followed by the number of files to
+    // a tab page.
+    pub fn parse(value: &str) -> Result<Layout, String> {
+        let invalid = || format!("invalid layout `{}`", value);
+        let mut words = value.split_whitespace();
+
+        let split = match words.next() {
+            Some("tab") if words.next().is_none() => {
+                return Ok(Layout {
+                    split: Split::Vertical,
+                    per_tab: Some(1),
+                });
+            }
+            Some("split") => Split::Horizontal,
+            Some("vsplit") => Split::Vertical,
+            _ => return Err(invalid()),
+        };
+
+        let per_tab = match words.next() {
+            Some(count) => Some(
+                count
+                    .parse()
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .ok_or_else(invalid)?,
+            ),
+            None => None,
+        };
+
+        if words.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(Layout { split, per_tab })
+    }
+}
+
+impl std::fmt::Display for Layout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let split = match self.split {
+            Split::Horizontal => "split",
+            Split::Vertical => "vsplit",
+        };
+
+        match self.per_tab {
+            Some(1) => write!(f, "tab"),
+            Some(count) => write!(f, "{} {}", split, count),
+            None => write!(f, "{}", split),
+        }
+    }
+}
+
+impl Split {
+    // The Ex command splitting off the next window, which goes to the right of or below the
+    // current one, in the order of the files.
+    pub fn command(self) -> &'static str {
+        match self {
+            Split::Horizontal => "rightbelow split",
+            Split::Vertical => "rightbelow vsplit",
+        }
+    }
+}
+
+impl Config {
+    // Where the config file lives: wherever GVI_CONFIG says, or else %APPDATA%\gvi\config.toml
+    // on Windows and $XDG_CONFIG_HOME/gvi/config.toml (~/.config/gvi/config.toml without it)
+    // elsewhere. Along with whether it has to exist, which is only for the one GVI_CONFIG names.
+    pub fn location(env: impl Fn(&str) -> Option<String>) -> Option<(PathBuf, bool)> {
+        if let Some(file) = env("GVI_CONFIG").filter(|file| !file.is_empty()) {
+            return Some((PathBuf::from(file), true));
+        }
+
+        #[cfg(target_os = "windows")]
+        let dir = env("APPDATA").map(PathBuf::from);
+
+        #[cfg(not(target_os = "windows"))]
+        let dir = env("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env("HOME").map(|home| Path::new(&home).join(".config")));
+
+        dir.map(|dir| (dir.join("gvi").join("config.toml"), false))
+    }
+
+    // A missing file is the same as an empty one.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Config::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Config, String> {
+        let mut config = Config::default();
+        let mut section = String::new();
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+
+                // A profile without settings is still one.
+                if let Some(profile) = section.strip_prefix("profiles.") {
+                    config.profiles.entry(unquote(profile.trim())).or_default();
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected `key = value`", i + 1));
+            };
+            let key = unquote(key.trim());
+            let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", i + 1, e))?;
+
+            match section.as_str() {
+                "" => config.options.push((key, value)),
+                name if name.starts_with("profiles.") => {
+                    let profile = unquote(name["profiles.".len()..].trim());
+                    config
+                        .profiles
+                        .entry(profile)
+                        .or_default()
+                        .push((key, value));
+                }
+                "routes" => {
+                    config
+                        .routes
+                        .insert(key.trim_start_matches('.').to_string(), value);
+                }
+                "presets" => {
+                    let layout =
+                        Layout::parse(&value).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                    config.presets.insert(key, layout);
+                }
+                _ => return Err(format!("line {}: unknown setting `{}`", i + 1, key)),
+            }
+        }
+
+        Ok(config)
+    }
+
+    // The top level settings as command line options: `key = true` is a plain `--key`.
+    pub fn args(&self) -> Vec<String> {
+        to_args(&self.options)
+    }
+
+    // Those of the profile, which go after the top level ones and so win over them.
+    pub fn profile_args(&self, name: &str) -> Option<Vec<String>> {
+        self.profiles.get(name).map(|options| to_args(options))
+    }
+
+    // The server configured for the file, if any.
+    pub fn route(&self, path: &Path) -> Option<&str> {
+        let ext = path.extension()?.to_str()?;
+
+        self.routes.get(ext).map(String::as_str)
+    }
+}
+
+fn to_args(options: &[(String, String)]) -> Vec<String> {
+    options
+        .iter()
+        .filter(|(_, value)| value != "false")
+        .map(|(key, value)| match value.as_str() {
+            "true" => format!("--{}", key),
+            _ => format!("--{}={}", key, value),
+        })
+        .collect()
+}
+
+pub fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn unquote(key: &str) -> String {
+    key.trim_matches('"').to_string()
+}
+
+// Strings have to be quoted; numbers and booleans are kept as written.
+fn parse_value(value: &str) -> Result<String, String> {
+    // Drop a trailing comment, unless it's part of a string.
+    let value = match value.strip_prefix('"') {
+        Some(rest) => return parse_string(rest),
+        None => value.split('#').next().unwrap_or_default().trim(),
+    };
+
+    if value.is_empty() {
+        return Err("missing value".to_string());
+    }
+
+    Ok(value.to_string())
+}
+
+fn parse_string(rest: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = rest.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(result),
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c @ ('"' | '\\')) => result.push(c),
+                _ => return Err("invalid escape sequence".to_string()),
+            },
+            c => result.push(c),
+        }
+    }
+
+    Err("unterminated string".to_string())
+}
@@ -0,0 +1,213 @@
+use std::path::{Path, PathBuf};
+
+// Patterns of an ignore file, in the syntax of .gitignore:
+//
+//     # comment
+//     *.log       a file (or directory) with this name anywhere below
+//     /build      only right next to the ignore file
+//     target/     directories only
+//     !keep.log   take back an earlier pattern
+#[derive(Debug)]
+pub struct IgnoreRules {
+    // The directory the ignore file is in, which anchored patterns are relative to.
+    base: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    // Matched against the whole path below the base rather than just the name.
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    pub const FILE_NAME: &str = ".gviignore";
+
+    // The rules of the ignore file in the directory, if it has one.
+    pub fn load(dir: &Path) -> Option<IgnoreRules> {
+        let content = std::fs::read_to_string(dir.join(Self::FILE_NAME)).ok()?;
+
+        Some(IgnoreRules::parse(dir, &content))
+    }
+
+    pub fn parse(base: &Path, content: &str) -> IgnoreRules {
+        let patterns = content
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+
+                Pattern {
+                    glob: line.trim_start_matches('/').chars().collect(),
+                    negated,
+                    dir_only,
+                    anchored: line.contains('/'),
+                }
+            })
+            .collect();
+
+        IgnoreRules {
+            base: base.to_path_buf(),
+            patterns,
+        }
+    }
+
+    // Whether the last pattern matching the path ignores it (or takes it back), if any does.
+    fn decide(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let relative: Vec<char> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+            .chars()
+            .collect();
+        let name: Vec<char> = path.file_name()?.to_string_lossy().chars().collect();
+
+        self.patterns
+            .iter()
+            .rev()
+            .filter(|pattern| is_dir || !pattern.dir_only)
+            .find(|pattern| {
+                let text = if pattern.anchored { &relative } else { &name };
+                glob_match(&pattern.glob, text)
+            })
+            .map(|pattern| !pattern.negated)
+    }
+}
+
+// Rules of deeper directories come later and win over the ones of their parents.
+pub fn is_ignored(rules: &[IgnoreRules], path: &Path, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find_map(|rules| rules.decide(path, is_dir))
+        .unwrap_or(false)
+}
+
+// Directories hardly anyone means to open the contents of, skipped unless told otherwise.
+pub const DEFAULT_DIRS: [&str; 5] = [".git", "target", "node_modules", ".svn", "__pycache__"];
+
+pub fn is_default_dir(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| DEFAULT_DIRS.iter().any(|dir| name == *dir))
+}
+
+// `*` and `?` stay within a path component, `**` crosses them.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // "a/**/b" also matches "a/b".
+            let (rest, at_boundary) = match rest {
+                ['/', rest @ ..] => (rest, true),
+                _ => (rest, false),
+            };
+
+            (0..=text.len())
+                .filter(|&i| !at_boundary || i == 0 || text[i - 1] == '/')
+                .any(|i| glob_match(rest, &text[i..]))
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => glob_match(rest, text),
+            _ => false,
+        },
+        ['[', class @ ..] => match (class.iter().position(|&c| c == ']'), text) {
+            // An empty class is a literal "]" in it, as in "[]]".
+            (Some(end), [c, text @ ..]) if end > 0 || class.len() > 1 => {
+                let (negated, members) = match &class[..end] {
+                    ['!' | '^', members @ ..] => (true, members),
+                    members => (false, members),
+                };
+
+                class_contains(members, *c) != negated
+                    && *c != '/'
+                    && glob_match(&class[end + 1..], text)
+            }
+            (None, ['[', text @ ..]) => glob_match(class, text),
+            _ => false,
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => glob_match(rest, text),
+            _ => false,
+        },
+    }
+}
+
+fn class_contains(members: &[char], c: char) -> bool {
+    let mut i = 0;
+
+    while i < members.len() {
+        if i + 2 < members.len() && members[i + 1] == '-' {
+            if (members[i]..=members[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if members[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        glob_match(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("*.log", "app.log"));
+        assert!(!matches("*.log", "logs/app.log"));
+        assert!(matches("logs/**/*.log", "logs/app.log"));
+        assert!(matches("logs/**/*.log", "logs/2024/01/app.log"));
+        assert!(matches("**/fixtures", "tests/data/fixtures"));
+        assert!(matches("build/**", "build/out/a.o"));
+        assert!(matches("file?.[ch]", "file1.c"));
+        assert!(!matches("file?.[!ch]", "file1.c"));
+        assert!(matches("v[0-9].txt", "v7.txt"));
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+    }
+
+    #[test]
+    fn later_patterns_and_deeper_files_win() {
+        let root = Path::new("/repo");
+        let rules = [
+            IgnoreRules::parse(root, "*.log\n!keep.log\n/dist\ntarget/\n"),
+            IgnoreRules::parse(&root.join("sub"), "keep.log\n"),
+        ];
+
+        assert!(is_ignored(&rules, &root.join("a.log"), false));
+        assert!(!is_ignored(&rules, &root.join("keep.log"), false));
+        assert!(is_ignored(&rules, &root.join("sub/keep.log"), false));
+        assert!(is_ignored(&rules, &root.join("dist"), true));
+        assert!(!is_ignored(&rules, &root.join("sub/dist"), true));
+        assert!(is_ignored(&rules, &root.join("sub/target"), true));
+        assert!(!is_ignored(&rules, &root.join("sub/target"), false));
+    }
+}
@@ -1,260 +1,8905 @@
-use std::path::PathBuf;
+mod capabilities;
+mod config;
+mod detect;
+mod ignore;
+mod manifest;
+
+use capabilities::{Capabilities, CapabilityCache};
+use config::{Config, Layout, Split};
+use detect::{CombinedDetector, InstanceDetector, ServerInfo, ServerListDetector};
+use ignore::IgnoreRules;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-#[derive(Default)]
-struct Gvim();
+trait Runner {
+    fn spawn(&mut self, command: Command) -> std::io::Result<()>;
+    // Run to completion, capturing what the command prints.
+    fn output(&mut self, command: Command) -> std::io::Result<CommandOutput>;
+}
 
-// I picked these values off the top of my head
-const MAX_ARGS: usize = 20;
-const MAX_FILES: usize = 30;
-const MAX_SIZE: u64 = 1024 * 300;
+struct CommandOutput {
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
 
-impl Gvim {
-    const PROCESS_RUNNING_TIME_THRESHOLD_IN_SECS: u64 = 3;
-    const GVIM_REUSE_INSTANCE_OPTIONS: [&str; 3] = ["--server-name", "GVIM", "--remote-tab"];
-    #[cfg(target_os = "windows")]
-    const DETACHED_PROCESS: u32 = 0x00000008;
+struct SystemRunner;
 
-    fn new() -> Self {
-        Gvim::default()
+impl Runner for SystemRunner {
+    fn spawn(&mut self, mut command: Command) -> std::io::Result<()> {
+        command.spawn().map(|_| ())
     }
 
-    fn check_process(&self) -> Option<u64> {
-        let mut system = sysinfo::System::new();
+    fn output(&mut self, mut command: Command) -> std::io::Result<CommandOutput> {
+        let output = command.output()?;
 
-        system.refresh_specifics(
-            sysinfo::RefreshKind::nothing()
-                .with_processes(sysinfo::ProcessRefreshKind::everything()),
-        );
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
 
-        // Let's check if there's already gvim instance or not
-        if let Some((_, p)) = system
-            .processes()
-            .iter()
-            .find(|(_, p)| p.name() == "gvim" || p.name() == "gvim.exe")
-        {
-            let run_secs = p.run_time();
+// What the waits for gvim to come up go by, so that tests don't have to wait.
+trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
 
-            Some(run_secs)
-        } else {
-            None
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+#[derive(Debug)]
+enum AppError {
+    MissingOptionValue(String),
+    InvalidOptionValue(String, String),
+    EditorNotFound,
+    OpenerNotFound(String),
+    EditorNotExecutable(PathBuf),
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    InvalidConfig(PathBuf, String),
+    // GVI_CONFIG names a file that doesn't exist.
+    MissingConfig(PathBuf),
+    InvalidManifest(PathBuf, String),
+    // Arguments which can't be opened, with the reason for each.
+    InvalidArguments(Vec<(PathBuf, String)>),
+    // More arguments than --max-args once ranges are counted, with the arguments to list (none
+    // unless the message is detailed).
+    TooManyArguments {
+        count: usize,
+        limit: usize,
+        listed: Vec<PathBuf>,
+    },
+    // More paths than --max-total while expanding the directory, with the files collected so
+    // far.
+    TooManyEntries {
+        dir: PathBuf,
+        collected: usize,
+        limit: usize,
+    },
+    ServerNotFound(String),
+    // A directory given as an argument along with --no-expand.
+    DirectoryArgument(PathBuf),
+    // --profile-name names a profile the config file doesn't have.
+    UnknownProfile(String),
+    // --preset names a layout the config file doesn't have.
+    UnknownPreset(String),
+    // Nothing matched the --fuzzy query.
+    NoMatch(String),
+    // --remote-only, and no server of that name to open the files in.
+    NoRunningServer(String),
+    // The server named first couldn't evaluate the expression, for the reason given last.
+    RemoteExprFailed(String, String, String),
+    // The server wouldn't take the new name, given second.
+    RenameRefused(String, String),
+    // A subcommand used the wrong way, with the usage to show.
+    InvalidUsage(&'static str),
+    CommandSpawnError(std::io::Error),
+    // Ctrl-C, after opening the given number of files.
+    Interrupted(usize),
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::MissingOptionValue(name) => write!(f, "Option {} requires a value.", name),
+            AppError::InvalidOptionValue(name, value) => {
+                write!(f, "Invalid value '{}' for option {}.", value, name)
+            }
+            AppError::EditorNotFound => write!(f, "It seems you don't have gvim executable."),
+            AppError::OpenerNotFound(name) => {
+                write!(f, "It seems you don't have {} executable.", name)
+            }
+            AppError::EditorNotExecutable(path) => {
+                write!(f, "{} is not executable.", path.display())
+            }
+            AppError::Io { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            AppError::InvalidConfig(path, message) => {
+                write!(f, "Invalid config file {}: {}", path.display(), message)
+            }
+            AppError::MissingConfig(path) => write!(
+                f,
+                "There is no config file {}, which GVI_CONFIG names.",
+                path.display()
+            ),
+            AppError::InvalidManifest(path, message) => {
+                write!(f, "Invalid manifest {}: {}", path.display(), message)
+            }
+            AppError::InvalidArguments(problems) => {
+                write!(f, "Nothing has been opened, because of these arguments:")?;
+                for (path, problem) in problems {
+                    write!(f, "\n  {}: {}", path.display(), problem)?;
+                }
+                Ok(())
+            }
+            AppError::TooManyArguments {
+                count,
+                limit,
+                listed,
+            } => {
+                write!(
+                    f,
+                    "Nothing has been opened, because there are {} arguments (at most {}).",
+                    count, limit
+                )?;
+                for item in listed {
+                    write!(f, "\n  {}", item.display())?;
+                }
+                Ok(())
+            }
+            AppError::TooManyEntries {
+                dir,
+                collected,
+                limit,
+            } => write!(
+                f,
+                "Nothing has been opened, because more than {} paths came up while expanding {} ({} files collected by then).\nPlease break down the arguments, e.g. to the subdirectories of it, or raise --max-total.",
+                limit,
+                dir.display(),
+                collected
+            ),
+            AppError::ServerNotFound(name) => write!(f, "There is no gvim server named {}.", name),
+            AppError::DirectoryArgument(path) => write!(
+                f,
+                "{} is a directory, and --no-expand keeps gvi from opening the files in it.",
+                path.display()
+            ),
+            AppError::UnknownProfile(name) => {
+                write!(f, "There is no profile named {} in the config file.", name)
+            }
+            AppError::UnknownPreset(name) => {
+                write!(f, "There is no preset named {} in the config file.", name)
+            }
+            AppError::NoMatch(query) => write!(f, "No file matches {}.", query),
+            AppError::NoRunningServer(name) => write!(
+                f,
+                "No gvim server named {} is running, and --remote-only keeps gvi from launching one.",
+                name
+            ),
+            AppError::RemoteExprFailed(server, expr, message) => write!(
+                f,
+                "gvim server {} couldn't evaluate {}: {}",
+                server, expr, message
+            ),
+            AppError::RenameRefused(old, new) => write!(
+                f,
+                "{} refused to take the name {}; gvim can't change the name of a running server.",
+                old, new
+            ),
+            AppError::InvalidUsage(usage) => write!(f, "Usage: {}", usage),
+            AppError::CommandSpawnError(e) => write!(f, "Failed to launch gvim: {}", e),
+            AppError::Interrupted(opened) => {
+                write!(f, "Interrupted after opening {} files.", opened)
+            }
         }
     }
+}
 
-    fn open(&self, normalized_paths: &Vec<PathBuf>) {
-        if let Some(running_time) = self.check_process() {
-            // Reuse a existing gvim instance.
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::Io { source, .. } => Some(source),
+            AppError::CommandSpawnError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-            // If no arguments have been supplied, there is nothing to do.
-            if normalized_paths.is_empty() {
-                return;
+// The last step: whatever the files have been expanded, filtered and ordered into is handed over
+// to an opener.
+trait Opener {
+    fn open(&mut self, files: &[PathBuf], opts: &Options) -> Result<(), AppError>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum OpenerKind {
+    #[default]
+    Gvim,
+    // VS Code, reusing its last active window.
+    Code,
+}
+
+impl OpenerKind {
+    fn parse(value: &str) -> Option<OpenerKind> {
+        match value {
+            "gvim" => Some(OpenerKind::Gvim),
+            "code" => Some(OpenerKind::Code),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            OpenerKind::Gvim => "gvim",
+            OpenerKind::Code => "code",
+        }
+    }
+}
+
+struct CodeOpener {
+    editor: PathBuf,
+    runner: Box<dyn Runner>,
+}
+
+impl Opener for CodeOpener {
+    fn open(&mut self, files: &[PathBuf], opts: &Options) -> Result<(), AppError> {
+        let mut command = Command::new(&self.editor);
+        command.arg("--reuse-window");
+
+        for file in files {
+            match opts.lines.get(file) {
+                Some(line) => {
+                    let mut target = file.clone().into_os_string();
+                    target.push(format!(":{}", line));
+                    command.arg("--goto").arg(target);
+                }
+                None => {
+                    command.arg(file);
+                }
             }
+        }
 
-            // Notice: just-launched gvim instance might have no remote functionalities yet.
-            // So for such cases we need to "wait" for a moment before the following execution.
-            // Not sure how long should we wait for but 3 seconds must be at most sufficient.
-            let rest = Self::PROCESS_RUNNING_TIME_THRESHOLD_IN_SECS.saturating_sub(running_time);
+        self.runner
+            .spawn(command)
+            .map_err(AppError::CommandSpawnError)
+    }
+}
 
-            std::thread::sleep(std::time::Duration::from_secs(rest));
+struct Gvim {
+    editor: PathBuf,
+    detector: Box<dyn InstanceDetector>,
+    runner: Box<dyn Runner>,
+    clock: Box<dyn Clock>,
+    // Upper bound of the total length of paths passed to a single gvim invocation.
+    arg_budget: usize,
+    // Name of the server to reuse (or to register a fresh instance under).
+    server_name: String,
+    // Whether the binary has +clientserver, once we've asked.
+    clientserver: Option<bool>,
+    cache: Option<CapabilityCache>,
+    warnings: Vec<Warning>,
+    // Set on Ctrl-C; checked before each gvim invocation.
+    interrupted: &'static AtomicBool,
+    // Files handed to gvim so far.
+    opened: usize,
+    // Turns our paths into the editor's, see --wsl.
+    translate: Option<fn(&Path) -> PathBuf>,
+    // Fresh instances launched under these server names, not seen registered yet; only kept
+    // for --max-concurrent-spawns.
+    launching: Vec<(String, Instant)>,
+    // Time spent looking for a running instance, for --profile.
+    detection: Duration,
+    // How long a fresh instance may take to accept remote commands.
+    startup_timeout: Duration,
+    // What was decided on the way, one line each; only kept for --explain.
+    explanation: Option<Vec<String>>,
+    // Leave the gvim invocations at the explanation.
+    dry: bool,
+    // A detection running on a thread of its own for the server named, which has the detector
+    // until it's joined.
+    pending: Option<(String, DetectionThread)>,
+}
 
-            self.exec_gvim(Self::GVIM_REUSE_INSTANCE_OPTIONS, normalized_paths);
-        } else {
-            // Create a new gvim instance.
+type DetectionThread = std::thread::JoinHandle<(Box<dyn InstanceDetector>, Option<ServerInfo>)>;
+
+// Stands in for the detector while a detection thread has it; never asked, since find_server
+// joins the thread first.
+struct LentDetector;
 
-            self.exec_gvim([""; 0], normalized_paths);
+impl InstanceDetector for LentDetector {
+    fn find_server(&self, _: &str) -> Option<ServerInfo> {
+        None
+    }
+}
+
+impl Default for Gvim {
+    fn default() -> Self {
+        Gvim {
+            editor: PathBuf::from("gvim"),
+            detector: Box::new(CombinedDetector::new(PathBuf::from("gvim"))),
+            runner: Box::new(SystemRunner),
+            clock: Box::new(SystemClock),
+            arg_budget: Gvim::ARG_LENGTH_BUDGET,
+            server_name: Gvim::GVIM_SERVER_NAME.to_string(),
+            clientserver: None,
+            cache: None,
+            warnings: vec![],
+            interrupted: &INTERRUPTED,
+            opened: 0,
+            translate: None,
+            launching: vec![],
+            detection: Duration::ZERO,
+            startup_timeout: Gvim::PROCESS_RUNNING_TIME_THRESHOLD,
+            explanation: None,
+            dry: false,
+            pending: None,
         }
     }
+}
 
-    fn exec_gvim<I, S, T, U>(&self, options: I, args: T)
-    where
-        I: IntoIterator<Item = S>,
-        S: AsRef<std::ffi::OsStr>,
-        T: IntoIterator<Item = U>,
-        U: AsRef<std::ffi::OsStr>,
-    {
-        #[cfg(target_os = "windows")]
-        {
-            use std::os::windows::process::CommandExt;
-            Command::new("gvim")
-                .args(options)
-                .args(args)
-                .creation_flags(Self::DETACHED_PROCESS)
-                .spawn();
+#[derive(Default)]
+struct Options {
+    // Skip instance detection when opening a single regular file.
+    fast: bool,
+    // Use this gvim binary instead of looking it up on PATH.
+    editor_path: Option<PathBuf>,
+    // Load files into the buffer list of an existing instance instead of opening tabs.
+    buffer: bool,
+    sort_by: Option<SortKey>,
+    reverse: bool,
+    // Fail on unreadable paths instead of skipping them.
+    strict: bool,
+    // Ask before opening more files than this.
+    confirm: Option<usize>,
+    // Never ask.
+    yes: bool,
+    // Quickfix list to take the files (and lines) from.
+    from_quickfix: Option<PathBuf>,
+    // JSON list of the files (and lines) to open, see manifest::parse.
+    manifest: Option<PathBuf>,
+    // Name of the layout in the config file to open the files in.
+    preset: Option<String>,
+    // The profile of the config file whose settings take the place of the top level ones.
+    profile_name: Option<String>,
+    // The layout to open the files in: given as --layout, or what the preset stands for, looked
+    // up once the config has been read.
+    layout: Option<Layout>,
+    // A tab page for the files of each argument.
+    tab_per_arg: bool,
+    // Index of the argument each file came from, for --tab-per-arg.
+    sources: HashMap<PathBuf, usize>,
+    // Line to place the cursor on, per file.
+    lines: HashMap<PathBuf, usize>,
+    // Filetype of the content read from stdin (given as "-").
+    filetype: Option<String>,
+    // Filetype to set, per file.
+    filetypes: HashMap<PathBuf, String>,
+    // Pattern to place the cursor on the first match of, in every file.
+    search: Option<String>,
+    // Pattern to search for, per file; given as file:/pattern or filled in from --search.
+    searches: HashMap<PathBuf, String>,
+    // Keep running and let gvim reload the files when they change.
+    watch: bool,
+    // Keep files of the same directory next to each other.
+    group_by_dir: bool,
+    server_name: Option<String>,
+    // Server name with {repo}, {branch} and {cwd} filled in from the working directory.
+    server_name_template: Option<String>,
+    // A server of its own for each tmux pane.
+    server_per_pane: bool,
+    // Take relative arguments from the server's working directory rather than ours.
+    relative_to_server: bool,
+    // Read NUL separated paths from stdin, as printed by `find -print0` or `fd -0`.
+    null: bool,
+    // Don't descend into subdirectories of directory arguments.
+    no_recurse: bool,
+    // Also open directory arguments themselves.
+    include_dirs: bool,
+    // Raise the window of an existing instance after opening the files in it.
+    focus: bool,
+    // Extensions (without the dot) of the files to take from directories; any if empty.
+    ext: Vec<String>,
+    // Take only the most recently modified file of each directory.
+    latest: bool,
+    // Open only the files whose paths match this best, see fuzzy_score.
+    fuzzy: Option<String>,
+    // How many of the best matches to open; one unless given.
+    fuzzy_n: Option<usize>,
+    // Ask which of the files to open, by number.
+    select: bool,
+    // Open every file without asking, despite --select.
+    all: bool,
+    // Warn when opening more tab pages than this in one window; TAB_WARNING unless given.
+    tab_warning: Option<usize>,
+    // How many fresh instances may be starting up at the same time.
+    max_concurrent_spawns: Option<usize>,
+    // Descend into .git, target and the like as well.
+    no_default_ignores: bool,
+    // Open the files in the order of the arguments, ignoring --sort-by, --group-by-dir and
+    // --reverse.
+    preserve_arg_order: bool,
+    opener: OpenerKind,
+    color: ColorChoice,
+    dedupe_by: DedupeKey,
+    // How directories are walked, which decides the order of their files.
+    order: Traversal,
+    // In an existing instance, open the files after this tab page rather than after the last.
+    into_tab: Option<usize>,
+    // In an existing instance, switch to the files it has loaded already rather than open them in
+    // another tab page.
+    no_tab_for_existing_buffer: bool,
+    limits: LimitConfig,
+    // Open files over --max-size-per-file anyway when given as arguments.
+    keep_large_args: bool,
+    // Milliseconds a fresh instance may take to accept remote commands.
+    startup_timeout: Option<u64>,
+    // Milliseconds to wait between the remote invocations, for servers that drop some otherwise.
+    open_delay_ms: Option<u64>,
+    // Go on with the first --max-args arguments when there are more.
+    truncate_args: bool,
+    // Open the files, in order, for as long as their sizes add up to no more than this, in place
+    // of --max-size.
+    until_size: Option<u64>,
+    // Take directory arguments for mistakes rather than expand them.
+    no_expand: bool,
+    limit_message_detail: MessageDetail,
+    // Don't let a fresh instance take the focus.
+    background: bool,
+    // Use the Windows gvim from WSL; decided by looking around if unset.
+    wsl: Option<bool>,
+    // Read the files in this encoding (gvim's ++enc) instead of detecting it.
+    encoding: Option<String>,
+    // Ex commands to run once the files are open, in order.
+    commands: Vec<String>,
+    // Compare the two files in diff mode.
+    diff: bool,
+    // Show the files side by side in one tab page.
+    vsplit_all: bool,
+    // Never launch a fresh instance.
+    remote_only: bool,
+    // Quit without saving, for the close subcommand.
+    force: bool,
+    // Have the server write a session file once the files are open.
+    record_session: Option<PathBuf>,
+    // Launch a fresh instance restoring this session file, then open the files in it.
+    open_session: Option<PathBuf>,
+    // Print the resolved settings instead of opening anything.
+    dump_config: bool,
+    // Print the files open in the server instead of opening anything.
+    list_opened: bool,
+    // Launch the server on its own, detached from gvi and the terminal, instead of opening anything.
+    keep_alive: bool,
+    // Print how many files would be opened instead of opening them.
+    count_only: bool,
+    // Print how many directories the expansion went through, and what came of it.
+    count_dirs: bool,
+    // Open compressed files as decompressed copies.
+    decompress: bool,
+    // Tell what gvi decides on the way to gvim, and the commands it would run instead of running
+    // them, unless they are to run as well.
+    explain: bool,
+    explain_and_open: bool,
+    // Write the files to open, one absolute path per line, for a later `gvi @file`.
+    save_list: Option<PathBuf>,
+    // Open the files changed since this git commit as well.
+    since_commit: Option<String>,
+    // Give the server named first the second name instead of opening anything.
+    rename_server: Option<(String, String)>,
+    verbose: bool,
+    // Print how long each phase took.
+    profile: bool,
+    // Leave out the warnings reported at the end of the run.
+    quiet: bool,
+    // Long names of the options that were given, in order.
+    given: Vec<String>,
+}
+
+// How much the messages about the limits say.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum MessageDetail {
+    // One line.
+    #[default]
+    Terse,
+    // The line, followed by the arguments or files that make up the count or size.
+    Detailed,
+}
+
+impl MessageDetail {
+    fn parse(value: &str) -> Option<MessageDetail> {
+        match value {
+            "terse" => Some(MessageDetail::Terse),
+            "detailed" => Some(MessageDetail::Detailed),
+            _ => None,
         }
+    }
 
-        #[cfg(target_os = "macos")]
-        {
-            Command::new("gvim").args(options).args(args).spawn();
+    fn name(self) -> &'static str {
+        match self {
+            MessageDetail::Terse => "terse",
+            MessageDetail::Detailed => "detailed",
         }
+    }
+}
 
-        #[cfg(target_os = "linux")]
-        {
-            Command::new("gvim")
-                .env("GDK_BACKEND", "x11")
-                .args(options)
-                .args(args)
-                .spawn();
+// What makes two files the same one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum DedupeKey {
+    #[default]
+    Path,
+    // Device and inode, so hard links count as one file.
+    #[cfg(unix)]
+    Inode,
+    // Byte-identical files count as one, whatever their paths.
+    Content,
+}
+
+impl DedupeKey {
+    fn parse(value: &str) -> Option<DedupeKey> {
+        match value {
+            "path" => Some(DedupeKey::Path),
+            #[cfg(unix)]
+            "inode" => Some(DedupeKey::Inode),
+            "content" => Some(DedupeKey::Content),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DedupeKey::Path => "path",
+            #[cfg(unix)]
+            DedupeKey::Inode => "inode",
+            DedupeKey::Content => "content",
         }
     }
 }
 
-struct App {
-    args: Vec<String>,
-    gvim: Gvim,
-    files: Vec<PathBuf>,
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SortKey {
+    // Newest first.
+    Mtime,
+    Name,
+    // Smallest first.
+    Size,
 }
 
-impl App {
-    fn new() -> App {
-        App {
-            args: std::env::args().collect(),
-            gvim: Gvim::new(),
-            files: vec![],
+impl SortKey {
+    fn parse(value: &str) -> Option<SortKey> {
+        match value {
+            "mtime" => Some(SortKey::Mtime),
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            _ => None,
         }
     }
 
-    fn has_too_many_arguments(&self) -> bool {
-        if self.args.len() > MAX_ARGS + 1 {
-            return true;
-        } else {
-            return false;
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Mtime => "mtime",
+            SortKey::Name => "name",
+            SortKey::Size => "size",
         }
     }
+}
 
-    fn has_large_size_of_files(&self) -> bool {
-        let mut sum = 0;
-        let mut res = false;
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Traversal {
+    // Each subdirectory's files in place, before those of its later siblings.
+    #[default]
+    DepthFirst,
+    // The files of each level before those of the next one down.
+    BreadthFirst,
+}
+
+impl Traversal {
+    fn parse(value: &str) -> Option<Traversal> {
+        match value {
+            "dfs" => Some(Traversal::DepthFirst),
+            "bfs" => Some(Traversal::BreadthFirst),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Traversal::DepthFirst => "dfs",
+            Traversal::BreadthFirst => "bfs",
+        }
+    }
+}
+
+// Where the value of a setting came from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Source {
+    Default,
+    Config,
+    Env,
+    Flag,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Source::Default => "default",
+            Source::Config => "config",
+            Source::Env => "env",
+            Source::Flag => "flag",
+        }
+    }
+}
+
+// Settings which fall back to an environment variable when neither given as a flag nor in the
+// config file.
+const ENV_SETTINGS: [(&str, &str); 4] = [
+    ("editor-path", "GVI_EDITOR_PATH"),
+    ("server-name", "GVI_PROJECT"),
+    ("startup-timeout", "GVI_STARTUP_TIMEOUT_MS"),
+    ("layout", "GVI_LAYOUT"),
+];
+
+impl Options {
+    fn parse(args: &[String]) -> Result<(Options, Vec<String>), AppError> {
+        let mut opts = Options::default();
+        let mut items = vec![];
+        let mut args = args.iter();
+        // Like Vim, `+N` places the cursor on line N of the file that follows it.
+        let mut line = None;
+
+        while let Some(arg) = args.next() {
+            // Accept both "--name value" and "--name=value".
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next().cloned())
+                    .ok_or_else(|| AppError::MissingOptionValue(name.to_string()))
+            };
+
+            match name {
+                "--fast" => opts.fast = true,
+                "--buffer" => opts.buffer = true,
+                "--sort-by" => {
+                    let key = value()?;
+                    opts.sort_by = Some(
+                        SortKey::parse(&key)
+                            .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), key))?,
+                    );
+                }
+                "--reverse" => opts.reverse = true,
+                "--strict" => opts.strict = true,
+                "--editor-path" => opts.editor_path = Some(PathBuf::from(value()?)),
+                "--confirm" => opts.confirm = Some(parse_number(name, value()?)?),
+                "-y" | "--yes" => opts.yes = true,
+                "--from-quickfix" => opts.from_quickfix = Some(PathBuf::from(value()?)),
+                "--manifest" => opts.manifest = Some(PathBuf::from(value()?)),
+                "--preset" => opts.preset = Some(value()?),
+                "--profile-name" => opts.profile_name = Some(value()?),
+                "--layout" => {
+                    let layout = value()?;
+                    opts.layout = Some(
+                        Layout::parse(&layout)
+                            .map_err(|_| AppError::InvalidOptionValue(name.to_string(), layout))?,
+                    );
+                }
+                "--tab-per-arg" => opts.tab_per_arg = true,
+                "--watch" => opts.watch = true,
+                "--fuzzy" => opts.fuzzy = Some(value()?),
+                "--warn-tabs" => opts.tab_warning = Some(parse_number(name, value()?)?),
+                "--search" => opts.search = Some(value()?),
+                "--max-concurrent-spawns" => {
+                    let n = value()?;
+                    opts.max_concurrent_spawns = Some(
+                        n.parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), n))?,
+                    );
+                }
+                "--select" => opts.select = true,
+                "--all" => opts.all = true,
+                "--fuzzy-n" => {
+                    let n = value()?;
+                    opts.fuzzy_n = Some(
+                        n.parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), n))?,
+                    );
+                }
+                "--group-by-dir" => opts.group_by_dir = true,
+                "--server-name" => opts.server_name = Some(value()?),
+                "--server-name-template" => opts.server_name_template = Some(value()?),
+                "--server-per-pane" => opts.server_per_pane = true,
+                "--relative-to-server" => opts.relative_to_server = true,
+                "-0" | "--null" => opts.null = true,
+                "--no-recurse" => opts.no_recurse = true,
+                "--include-dirs" => opts.include_dirs = true,
+                "--focus" => opts.focus = true,
+                "--cmd" => opts.commands.push(value()?),
+                "--diff" => opts.diff = true,
+                "--vsplit-all" => opts.vsplit_all = true,
+                "--remote-only" => opts.remote_only = true,
+                "--force" => opts.force = true,
+                "--rename-server" => {
+                    let old = value()?;
+                    let new = args
+                        .next()
+                        .cloned()
+                        .filter(|new| !new.is_empty())
+                        .ok_or_else(|| AppError::MissingOptionValue(name.to_string()))?;
+                    opts.rename_server = Some((old, new));
+                }
+                "--since-commit" => opts.since_commit = Some(value()?),
+                "--save-list" => opts.save_list = Some(PathBuf::from(value()?)),
+                "--record-session" => opts.record_session = Some(PathBuf::from(value()?)),
+                "--open-session" => opts.open_session = Some(PathBuf::from(value()?)),
+                "--ext" => {
+                    opts.ext = value()?
+                        .split(',')
+                        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                        .filter(|ext| !ext.is_empty())
+                        .collect();
+                }
+                "--latest" => opts.latest = true,
+                "--no-default-ignores" => opts.no_default_ignores = true,
+                "--preserve-arg-order" => opts.preserve_arg_order = true,
+                "--opener" => {
+                    let kind = value()?;
+                    opts.opener = OpenerKind::parse(&kind)
+                        .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), kind))?;
+                }
+                "--order" => {
+                    let order = value()?;
+                    opts.order = Traversal::parse(&order)
+                        .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), order))?;
+                }
+                "--dedupe-by" => {
+                    let key = value()?;
+                    opts.dedupe_by = DedupeKey::parse(&key)
+                        .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), key))?;
+                }
+                "--into-tab" => {
+                    let tab = value()?;
+                    opts.into_tab = Some(
+                        tab.parse()
+                            .ok()
+                            .filter(|&tab| tab > 0)
+                            .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), tab))?,
+                    );
+                }
+                "--no-tab-for-existing-buffer" => opts.no_tab_for_existing_buffer = true,
+                "--max-args" => opts.limits.max_args = parse_number(name, value()?)?,
+                "--max-files" => opts.limits.max_files = parse_number(name, value()?)?,
+                "--max-size" => opts.limits.max_size = parse_number(name, value()?)? as u64,
+                "--max-depth" => opts.limits.max_depth = Some(parse_number(name, value()?)?),
+                "--max-total" => opts.limits.max_total = parse_number(name, value()?)?,
+                "--max-size-per-file" => {
+                    opts.limits.max_size_per_file = Some(parse_number(name, value()?)? as u64);
+                }
+                "--max-name-length" => {
+                    opts.limits.max_name_length = Some(parse_number(name, value()?)?);
+                }
+                "--keep-large-args" => opts.keep_large_args = true,
+                "--truncate-args" => opts.truncate_args = true,
+                "--until-size" => opts.until_size = Some(parse_number(name, value()?)? as u64),
+                "--startup-timeout" => {
+                    let millis = value()?;
+                    opts.startup_timeout = Some(
+                        millis
+                            .parse()
+                            .map_err(|_| AppError::InvalidOptionValue(name.to_string(), millis))?,
+                    );
+                }
+                "--open-delay-ms" => {
+                    opts.open_delay_ms = Some(parse_number(name, value()?)? as u64);
+                }
+                "--no-expand" => opts.no_expand = true,
+                "--limit-message-detail" => {
+                    let detail = value()?;
+                    opts.limit_message_detail = MessageDetail::parse(&detail)
+                        .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), detail))?;
+                }
+                "--background" => opts.background = true,
+                "--wsl" => opts.wsl = Some(true),
+                "--no-wsl" => opts.wsl = Some(false),
+                "--ft" => {
+                    let filetype = value()?;
 
-        self.files.iter().for_each(|f| match std::fs::metadata(f) {
-            Ok(metadata) => {
-                let size = metadata.len();
+                    if !is_plausible_filetype(&filetype) {
+                        return Err(AppError::InvalidOptionValue(name.to_string(), filetype));
+                    }
+
+                    opts.filetype = Some(filetype);
+                }
+                "--encoding" => {
+                    let encoding = value()?;
 
-                sum += size;
+                    if !is_plausible_encoding(&encoding) {
+                        return Err(AppError::InvalidOptionValue(name.to_string(), encoding));
+                    }
 
-                if sum > MAX_SIZE {
-                    res = true;
+                    opts.encoding = Some(encoding);
+                }
+                "--color" => {
+                    let choice = value()?;
+                    opts.color = ColorChoice::parse(&choice)
+                        .ok_or_else(|| AppError::InvalidOptionValue(name.to_string(), choice))?;
+                }
+                "--dump-config" => opts.dump_config = true,
+                "--list-opened" => opts.list_opened = true,
+                "--keep-alive" => opts.keep_alive = true,
+                "--count-only" => opts.count_only = true,
+                "--explain" => opts.explain = true,
+                "--explain-and-open" => opts.explain_and_open = true,
+                "--count-dirs" => opts.count_dirs = true,
+                "--decompress" => opts.decompress = true,
+                "--verbose" => opts.verbose = true,
+                "--quiet" => opts.quiet = true,
+                "--profile" => opts.profile = true,
+                _ => {
+                    if let Some(number) = arg.strip_prefix('+').and_then(|n| n.parse().ok()) {
+                        line = Some(number);
+                        continue;
+                    }
+                    if let Some(line) = line.take() {
+                        opts.lines.insert(PathBuf::from(arg), line);
+                    }
+                    items.push(arg.clone());
+                    continue;
                 }
             }
-            Err(_) => {}
-        });
 
-        res
+            opts.given.push(match name {
+                "-y" => "yes".to_string(),
+                "-0" => "null".to_string(),
+                _ => name.trim_start_matches("--").to_string(),
+            });
+        }
+
+        Ok((opts, items))
     }
 
-    fn open(&self) {
-        let files = &self.files;
-        self.gvim.open(&files);
+    // Whether a file found in a directory passes --ext.
+    fn wants_extension(&self, path: &Path) -> bool {
+        self.ext.is_empty()
+            || path
+                .extension()
+                .is_some_and(|ext| self.ext.contains(&ext.to_string_lossy().to_lowercase()))
     }
 
-    fn run(&mut self) {
-        if !which::which("gvim").unwrap().exists() {
-            eprintln!("Error: It seems you don't have gvim executable.");
-            std::process::exit(1);
-        }
+    // Whether a file found in a directory is a candidate for --fuzzy at all.
+    fn could_match(&self, path: &Path) -> bool {
+        self.fuzzy
+            .as_deref()
+            .is_none_or(|query| fuzzy_score(query, path).is_some())
+    }
 
-        // check if there's too many arguments
-        if self.has_too_many_arguments() {
-            std::process::exit(1);
+    // What was given for a file goes with it to where it's opened from instead.
+    fn move_file_settings(&mut self, from: &Path, to: &Path) {
+        if let Some(line) = self.lines.remove(from) {
+            self.lines.insert(to.to_path_buf(), line);
+        }
+        if let Some(pattern) = self.searches.remove(from) {
+            self.searches.insert(to.to_path_buf(), pattern);
+        }
+        if let Some(source) = self.sources.remove(from) {
+            self.sources.insert(to.to_path_buf(), source);
+        }
+        if let Some(filetype) = self.filetypes.remove(from) {
+            self.filetypes.insert(to.to_path_buf(), filetype);
         }
+    }
 
-        // split the necessary part of the args.
-        let items: Vec<String> = self.args[1..].to_vec();
+    // The settings worth showing, by long option name, formatted as TOML values. Unset ones
+    // have no value.
+    fn settings(&self) -> Vec<(&'static str, Option<String>)> {
+        let flag = |value: bool| Some(value.to_string());
+        let path = |value: &Option<PathBuf>| {
+            value
+                .as_ref()
+                .map(|path| config::quote(&path.to_string_lossy()))
+        };
 
-        let mut count: usize = 0;
+        vec![
+            ("fast", flag(self.fast)),
+            ("editor-path", path(&self.editor_path)),
+            ("buffer", flag(self.buffer)),
+            ("sort-by", self.sort_by.map(|key| config::quote(key.name()))),
+            ("reverse", flag(self.reverse)),
+            ("strict", flag(self.strict)),
+            ("confirm", self.confirm.map(|n| n.to_string())),
+            ("yes", flag(self.yes)),
+            ("from-quickfix", path(&self.from_quickfix)),
+            ("manifest", path(&self.manifest)),
+            ("preset", self.preset.as_deref().map(config::quote)),
+            (
+                "profile-name",
+                self.profile_name.as_deref().map(config::quote),
+            ),
+            (
+                "layout",
+                self.layout.map(|layout| config::quote(&layout.to_string())),
+            ),
+            ("tab-per-arg", flag(self.tab_per_arg)),
+            ("watch", flag(self.watch)),
+            ("group-by-dir", flag(self.group_by_dir)),
+            (
+                "server-name",
+                self.server_name.as_deref().map(config::quote),
+            ),
+            (
+                "server-name-template",
+                self.server_name_template.as_deref().map(config::quote),
+            ),
+            ("server-per-pane", flag(self.server_per_pane)),
+            ("relative-to-server", flag(self.relative_to_server)),
+            ("null", flag(self.null)),
+            ("no-recurse", flag(self.no_recurse)),
+            ("include-dirs", flag(self.include_dirs)),
+            ("focus", flag(self.focus)),
+            (
+                "since-commit",
+                self.since_commit.as_deref().map(config::quote),
+            ),
+            (
+                "save-list",
+                self.save_list
+                    .as_deref()
+                    .map(|path| config::quote(&path.to_string_lossy())),
+            ),
+            (
+                "open-session",
+                self.open_session
+                    .as_deref()
+                    .map(|path| config::quote(&path.to_string_lossy())),
+            ),
+            (
+                "record-session",
+                self.record_session
+                    .as_deref()
+                    .map(|path| config::quote(&path.to_string_lossy())),
+            ),
+            (
+                "ext",
+                Some(&self.ext)
+                    .filter(|ext| !ext.is_empty())
+                    .map(|ext| config::quote(&ext.join(","))),
+            ),
+            ("latest", flag(self.latest)),
+            ("decompress", flag(self.decompress)),
+            ("fuzzy", self.fuzzy.as_deref().map(config::quote)),
+            ("fuzzy-n", self.fuzzy_n.map(|n| n.to_string())),
+            ("select", flag(self.select)),
+            ("all", flag(self.all)),
+            ("no-default-ignores", flag(self.no_default_ignores)),
+            ("preserve-arg-order", flag(self.preserve_arg_order)),
+            ("opener", Some(config::quote(self.opener.name()))),
+            ("color", Some(config::quote(self.color.name()))),
+            ("dedupe-by", Some(config::quote(self.dedupe_by.name()))),
+            ("order", Some(config::quote(self.order.name()))),
+            ("into-tab", self.into_tab.map(|tab| tab.to_string())),
+            (
+                "no-tab-for-existing-buffer",
+                flag(self.no_tab_for_existing_buffer),
+            ),
+            ("background", flag(self.background)),
+            ("wsl", self.wsl.map(|wsl| wsl.to_string())),
+            ("ft", self.filetype.as_deref().map(config::quote)),
+            ("search", self.search.as_deref().map(config::quote)),
+            ("encoding", self.encoding.as_deref().map(config::quote)),
+            ("diff", flag(self.diff)),
+            ("vsplit-all", flag(self.vsplit_all)),
+            ("remote-only", flag(self.remote_only)),
+            ("force", flag(self.force)),
+            // A single line is all a config key can hold, which Vim's | is for.
+            (
+                "cmd",
+                Some(&self.commands)
+                    .filter(|commands| !commands.is_empty())
+                    .map(|commands| config::quote(&commands.join(" | "))),
+            ),
+            ("max-args", Some(self.limits.max_args.to_string())),
+            ("max-files", Some(self.limits.max_files.to_string())),
+            ("max-size", Some(self.limits.max_size.to_string())),
+            (
+                "max-depth",
+                self.limits.max_depth.map(|depth| depth.to_string()),
+            ),
+            ("max-total", Some(self.limits.max_total.to_string())),
+            (
+                "warn-tabs",
+                Some(self.tab_warning.unwrap_or(TAB_WARNING).to_string()),
+            ),
+            (
+                "max-concurrent-spawns",
+                self.max_concurrent_spawns.map(|n| n.to_string()),
+            ),
+            (
+                "max-size-per-file",
+                self.limits.max_size_per_file.map(|size| size.to_string()),
+            ),
+            (
+                "max-name-length",
+                self.limits.max_name_length.map(|n| n.to_string()),
+            ),
+            ("keep-large-args", flag(self.keep_large_args)),
+            ("truncate-args", flag(self.truncate_args)),
+            ("until-size", self.until_size.map(|size| size.to_string())),
+            (
+                "startup-timeout",
+                self.startup_timeout.map(|millis| millis.to_string()),
+            ),
+            (
+                "open-delay-ms",
+                self.open_delay_ms.map(|millis| millis.to_string()),
+            ),
+            ("no-expand", flag(self.no_expand)),
+            ("quiet", flag(self.quiet)),
+            ("profile", flag(self.profile)),
+            ("count-dirs", flag(self.count_dirs)),
+            (
+                "limit-message-detail",
+                Some(config::quote(self.limit_message_detail.name())),
+            ),
+        ]
+    }
+}
 
-        // expand all the items (including internal ones) if each of them is a directory.
-        self.files = items
-            .iter()
-            .take(MAX_FILES)
-            .filter_map(|item| {
-                let p = PathBuf::from(item);
-
-                // In Windows environment, .canonicalize() returns an abs path with a special prefix \\?\ to express extended-length path.
-                // But seemingly this kind of path doens't work properly for gvim so I don't adopt this method.
-                // match p.canonicalize() {
-                //     Ok(abs_p) => Some(abs_p),
-                //     Err(_) => None
-                // }
-
-                // We decided not to manipulate specified paths.
-                if p.exists() { return Some(p) } else { None }
-            })
-            .flat_map(|p| expand_dir(p, &mut count))
-            .collect();
+// gvim knows many more encodings than we'd like to list, but their names are all short words
+// like "latin1", "shift_jis" or "cp1252". Anything else would end up in a command line.
+fn is_plausible_encoding(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
 
-        // check if total size of the files is small enough to be acceptable
-        if self.has_large_size_of_files() {
-            std::process::exit(1);
-        }
+// Filetypes are names like "yaml", "cpp" or "c.doxygen".
+fn is_plausible_filetype(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
 
-        self.open();
+// Keys making the server edit each of the files again with the encoding: in its tab page, or
+// in the current window in --buffer mode.
+fn reload_with_encoding_keys(files: &[PathBuf], encoding: &str, buffer: bool) -> String {
+    let open = if buffer { "buffer" } else { "tab drop" };
+    let mut keys = "<C-\\><C-N>".to_string();
 
-        std::process::exit(0);
+    for file in files {
+        keys.push_str(&format!(
+            ":exe '{} ' . fnameescape({}) | e ++enc={}<CR>",
+            open,
+            vim_string_keys(file),
+            encoding
+        ));
     }
+
+    keys
 }
 
-// Support recursion
-fn expand_dir(maybe_dir: PathBuf, count: &mut usize) -> Vec<PathBuf> {
-    // if the given argument eventually becomes a file, return the value immediately.
-    // is_file will traverse symbolic link.
-    if maybe_dir.is_file() {
-        let file = maybe_dir;
-        *count += 1;
-        return vec![file];
+// Keys opening each file in a tab page of its own after the current one, or after tab page
+// `tab` to begin with.
+fn tabedit_keys(
+    files: &[PathBuf],
+    tab: Option<usize>,
+    opts: &Options,
+    target: impl Fn(&Path) -> PathBuf,
+) -> String {
+    let mut keys = "<C-\\><C-N>".to_string();
+
+    if let Some(tab) = tab {
+        keys.push_str(&format!(":tabnext {}<CR>", tab));
     }
 
-    // if the given argument is not readable (i.e. non-directory, lack of permissions) then ignore.
-    let Ok(read_dir) = std::fs::read_dir(maybe_dir) else {
-        return vec![];
+    let encoding = match &opts.encoding {
+        Some(encoding) => format!("++enc={} ", encoding),
+        None => String::new(),
     };
 
-    // expand dir(s)
-    let result: Vec<PathBuf> = read_dir
-        .into_iter()
-        .take(MAX_FILES)
-        .filter_map(|entry| {
-            match entry {
-                Ok(ent) => Some(ent),
-                Err(_) => None,
-            }
+    for file in files {
+        let line = match opts.lines.get(file) {
+            Some(line) => format!("+{} ", line),
+            None => String::new(),
+        };
+
+        keys.push_str(&format!(
+            ":exe 'tabedit {}{}' . fnameescape({})<CR>",
+            encoding,
+            line,
+            vim_string_keys(&target(file))
+        ));
+
+        if let Some(pattern) = opts.searches.get(file).filter(|_| line.is_empty()) {
+            keys.push_str(&format!(
+                "/{}<CR>",
+                search_pattern(pattern).replace('<', "<lt>")
+            ));
+        }
+    }
+
+    keys
+}
+
+// A tab page comparing the files side by side, like gvim -d would show them. :diffsplit turns
+// on diff mode in both windows.
+fn diff_keys(files: &[PathBuf], target: impl Fn(&Path) -> PathBuf) -> String {
+    split_keys(files, "vert diffsplit", target)
+}
+
+// A tab page with a window for each file, split off from the one before with the given command.
+fn split_keys(files: &[PathBuf], split: &str, target: impl Fn(&Path) -> PathBuf) -> String {
+    let mut keys = "<C-\\><C-N>".to_string();
+
+    for (i, file) in files.iter().enumerate() {
+        let command = if i == 0 { "tabedit" } else { split };
+
+        keys.push_str(&format!(
+            ":exe '{} ' . fnameescape({})<CR>",
+            command,
+            vim_string_keys(&target(file))
+        ));
+    }
+
+    keys
+}
+
+// Side by side, in order, like gvim -O shows them, with the cursor in the first window.
+fn vsplit_keys(files: &[PathBuf], target: impl Fn(&Path) -> PathBuf) -> String {
+    split_keys(files, "rightbelow vsplit", target) + ":1wincmd w<CR>"
+}
+
+// A pattern as it goes after the / of a search: a / in it would end the pattern, and so would a
+// line break.
+fn search_pattern(pattern: &str) -> String {
+    pattern.replace('/', "\\/").replace('\n', "\\n")
+}
+
+// The search, typed into the server in Normal mode.
+fn search_keys(pattern: &str) -> String {
+    format!(
+        "<C-\\><C-N>/{}<CR>",
+        search_pattern(pattern).replace('<', "<lt>")
+    )
+}
+
+// An argument like src/main.rs:/fn main names a file and a pattern to search for in it. A path
+// of its own wins, and so does a longer file, since the pattern may hold a :/ as well. Relative
+// paths are looked for in `base`.
+fn split_search(item: &Path, base: &Path) -> Option<(PathBuf, String)> {
+    let item = item.to_str()?;
+
+    if base.join(item).exists() {
+        return None;
+    }
+
+    item.match_indices(":/")
+        .map(|(at, _)| (&item[..at], &item[at + 2..]))
+        .find(|(file, pattern)| {
+            !file.is_empty() && !pattern.is_empty() && base.join(file).is_file()
         })
-        .flat_map(|ent| {
-            *count += 1;
+        .map(|(file, pattern)| (PathBuf::from(file), pattern.to_string()))
+}
 
-            // we probably never try to handle overcomplicated directory structure with this
-            // program so this is sufficient (I don't know).
-            if *count > 100 {
-                eprintln!("Error: It seems you are trying to expand directories with a complicated structure, but we regard this as an error.\nPlease break down the arguments and perform this program for smaller amount of objects.");
-                std::process::exit(1);
+// The program and its arguments, the ones with spaces (or nothing) in them quoted.
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            match arg.is_empty() || arg.contains(char::is_whitespace) {
+                true => config::quote(&arg),
+                false => arg.into_owned(),
             }
-
-            expand_dir(ent.path(), count)
         })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Ex commands, typed into the server in Normal mode.
+fn command_keys(commands: &[String]) -> String {
+    let mut keys = "<C-\\><C-N>".to_string();
+
+    for command in commands {
+        keys.push_str(&format!(":{}<CR>", command.replace('<', "<lt>")));
+    }
+
+    keys
+}
+
+// The tab pages to lay the files out in, with the split between the windows of each: so many
+// files to a tab page with --preset, or those of each argument with --tab-per-arg.
+fn tab_groups(paths: &[PathBuf], opts: &Options) -> Option<(Vec<Vec<PathBuf>>, Split)> {
+    if opts.diff {
+        return None;
+    }
+
+    if let Some(layout) = opts.layout {
+        let per_tab = layout.per_tab.unwrap_or(paths.len()).max(1);
+        let groups = paths.chunks(per_tab).map(<[PathBuf]>::to_vec).collect();
+        return Some((groups, layout.split));
+    }
+
+    if !opts.tab_per_arg {
+        return None;
+    }
+
+    // In order of first appearance, like routes, and split no further than --vsplit-all.
+    let mut groups: Vec<(Option<usize>, Vec<PathBuf>)> = vec![];
+    for path in paths {
+        let source = opts.sources.get(path).copied();
+
+        match groups.iter_mut().find(|(other, _)| *other == source) {
+            Some((_, files)) => files.push(path.clone()),
+            None => groups.push((source, vec![path.clone()])),
+        }
+    }
+
+    let groups = groups
+        .iter()
+        .flat_map(|(_, files)| files.chunks(MAX_SPLITS).map(<[PathBuf]>::to_vec))
         .collect();
+    Some((groups, Split::Vertical))
+}
+
+// Ex commands opening each group of files in a tab page of its own, except the first in a fresh
+// instance, which has just its empty one. The first of them is shown.
+fn layout_commands(
+    groups: &[Vec<PathBuf>],
+    split: Split,
+    fresh: bool,
+    target: impl Fn(&Path) -> PathBuf,
+) -> Vec<String> {
+    let mut commands = vec![];
+
+    for (i, group) in groups.iter().enumerate() {
+        for (j, file) in group.iter().enumerate() {
+            let command = match (i, j) {
+                (0, 0) if fresh => "edit",
+                (_, 0) => "tabedit",
+                _ => split.command(),
+            };
+            let path = target(file).to_string_lossy().replace('\'', "''");
+
+            commands.push(format!("exe '{} ' . fnameescape('{}')", command, path));
+        }
+
+        if group.len() > 1 {
+            commands.push("1wincmd w".to_string());
+        }
+    }
 
-    return result;
+    if groups.len() > 1 {
+        commands.push(format!(
+            "exe 'tabnext ' . (tabpagenr() - {})",
+            groups.len() - 1
+        ));
+    }
+
+    commands
 }
 
-fn main() {
-    let mut app = App::new();
-    app.run();
+// A path as a Vim string literal, written as --remote-send keys.
+fn vim_string_keys(path: &Path) -> String {
+    let path = path
+        .to_string_lossy()
+        .replace('\'', "''")
+        .replace('<', "<lt>");
+
+    format!("'{}'", path)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// The first line of Vim's error messages, like "E449: Invalid expression received".
+fn vim_error(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .map(str::trim)
+        .find(|line| {
+            line.strip_prefix('E')
+                .and_then(|rest| rest.split_once(':'))
+                .is_some_and(|(code, _)| {
+                    !code.is_empty() && code.chars().all(|c| c.is_ascii_digit())
+                })
+        })
+        .map(str::to_string)
+}
 
-    #[test]
-    fn fail_to_open_large_file() {
-        let mut app = App::new();
-        app.files = vec![PathBuf::from("tests/test_asset/huge_file.txt")];
-        assert!(app.has_large_size_of_files());
+// For the remote calls that are only nice to have: a server that can't answer them is no reason
+// to stop, but failing to run gvim at all still is.
+fn tolerate_remote_failure(result: Result<String, AppError>) -> Result<String, AppError> {
+    match result {
+        Err(AppError::RemoteExprFailed(..)) => Ok(String::new()),
+        result => result,
+    }
+}
+
+// tmux names its panes %0, %1 and so on, which makes GVIM_PANE0, GVIM_PANE1 and so on.
+fn pane_server_name(pane: &str) -> Option<String> {
+    let id: String = pane.chars().filter(char::is_ascii_alphanumeric).collect();
+
+    (!id.is_empty()).then(|| format!("{}_PANE{}", Gvim::GVIM_SERVER_NAME, id))
+}
+
+fn parse_number(name: &str, value: String) -> Result<usize, AppError> {
+    value
+        .parse()
+        .map_err(|_| AppError::InvalidOptionValue(name.to_string(), value))
+}
+
+// What opening the files comes down to, worked out before any of it happens.
+#[derive(Debug, PartialEq)]
+struct OpenPlan {
+    targets: Vec<PlannedTarget>,
+}
+
+// The files of one server, in the order they are opened.
+#[derive(Debug, PartialEq)]
+struct PlannedTarget {
+    server: String,
+    instance: Instance,
+    // How the instance was decided on, one step per line, for --explain.
+    explanation: Vec<String>,
+    files: Vec<PlannedFile>,
+}
+
+#[derive(Debug, PartialEq)]
+struct PlannedFile {
+    path: PathBuf,
+    // Where the cursor goes, and the filetype to set.
+    line: Option<usize>,
+    search: Option<String>,
+    filetype: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instance {
+    // An instance which has been running for the given seconds.
+    Existing(u64),
+    Fresh,
+}
+
+// Only there to catch runaway input like `gvi $(find /)` or `{1..100000}`, which counts for its
+// 100000 members before any of them is built: what gets opened is limited by MAX_FILES and
+// MAX_SIZE.
+const MAX_ARGS: usize = 1000;
+
+// I picked these values off the top of my head
+const MAX_FILES: usize = 30;
+const MAX_SIZE: u64 = 1024 * 300;
+// we probably never try to handle overcomplicated directory structure with this
+// program so this is sufficient (I don't know).
+const MAX_ENTRIES: usize = 100;
+
+// The limits above, for one run. Each can be changed with the option of the same name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct LimitConfig {
+    // Arguments, after expanding ranges.
+    max_args: usize,
+    // Files to open in total, and taken from each directory.
+    max_files: usize,
+    // Total size of the files in bytes.
+    max_size: u64,
+    // Levels of directories to descend below a directory argument; no limit if unset.
+    max_depth: Option<usize>,
+    // Size of any one file in bytes; no limit if unset.
+    max_size_per_file: Option<u64>,
+    // Characters in the name of a file found in a directory; no limit if unset.
+    max_name_length: Option<usize>,
+    // Paths looked at while expanding directories.
+    max_total: usize,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        LimitConfig {
+            max_args: MAX_ARGS,
+            max_files: MAX_FILES,
+            max_size: MAX_SIZE,
+            max_depth: None,
+            max_size_per_file: None,
+            max_name_length: None,
+            max_total: MAX_ENTRIES,
+        }
+    }
+}
+
+// How many tab pages a window takes before opening more is worth a warning. More than fit the
+// tab line of a wide window, about.
+const TAB_WARNING: usize = 15;
+
+// How many windows --vsplit-all puts side by side in one tab page. Beyond that they get too
+// narrow to read.
+const MAX_SPLITS: usize = 6;
+
+impl Gvim {
+    const PROCESS_RUNNING_TIME_THRESHOLD: Duration = Duration::from_secs(3);
+    const REGISTRATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+    const REMOTE_EXPR_ATTEMPTS: usize = 3;
+    const GVIM_SERVER_NAME: &str = detect::DEFAULT_SERVER_NAME;
+    // What to look for on PATH, most preferred first. MacVim's mvim takes the same arguments;
+    // nvim-qt has no client-server (see Capabilities::of_editor), so it always gets fresh instances.
+    const EDITORS: [&str; 3] = ["gvim", "mvim", "nvim-qt"];
+    // What to look for on PATH under WSL, which includes the Windows one.
+    const WINDOWS_EDITOR: &str = "gvim.exe";
+    // Windows limits the whole command line to 32767 characters. Unix systems allow much more
+    // (ARG_MAX), but also count the environment against it, so stay well below.
+    #[cfg(target_os = "windows")]
+    const ARG_LENGTH_BUDGET: usize = 24 * 1024;
+    #[cfg(not(target_os = "windows"))]
+    const ARG_LENGTH_BUDGET: usize = 96 * 1024;
+    // Listed buffers with a name, one per line; scratch and help buffers are left out.
+    const LIST_BUFFERS_EXPR: &str = r#"join(map(filter(getbufinfo({'buflisted': 1}), 'v:val.name != ""'), 'v:val.name'), "\n")"#;
+    #[cfg(target_os = "windows")]
+    const DETACHED_PROCESS: u32 = 0x00000008;
+    // Ignores the Ctrl-C of the console it was launched from.
+    #[cfg(target_os = "windows")]
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+
+    fn new() -> Self {
+        Gvim::default()
+    }
+
+    // An explicitly given editor path (--editor-path, then $GVI_EDITOR_PATH) bypasses the PATH
+    // lookup, but it still has to be something we can actually execute.
+    fn resolve_editor(
+        opts: &Options,
+        env: impl Fn(&str) -> Option<String>,
+        which: impl Fn(&str) -> Option<PathBuf>,
+    ) -> Result<PathBuf, AppError> {
+        let explicit = opts
+            .editor_path
+            .clone()
+            .or_else(|| env("GVI_EDITOR_PATH").map(PathBuf::from));
+
+        let Some(path) = explicit else {
+            return Self::EDITORS
+                .iter()
+                .find_map(|name| which(name))
+                .ok_or(AppError::EditorNotFound);
+        };
+
+        if !path.is_file() {
+            return Err(AppError::EditorNotFound);
+        }
+
+        if !is_executable(&path) {
+            return Err(AppError::EditorNotExecutable(path));
+        }
+
+        Ok(path)
+    }
+
+    // --startup-timeout, then $GVI_STARTUP_TIMEOUT_MS, in milliseconds. A value of the variable
+    // that isn't one is warned about and passed over.
+    fn resolve_startup_timeout(
+        opts: &Options,
+        env: impl Fn(&str) -> Option<String>,
+        warnings: &mut Vec<Warning>,
+    ) -> Duration {
+        if let Some(millis) = opts.startup_timeout {
+            return Duration::from_millis(millis);
+        }
+
+        match env("GVI_STARTUP_TIMEOUT_MS").filter(|value| !value.is_empty()) {
+            Some(value) => match value.trim().parse() {
+                Ok(millis) => Duration::from_millis(millis),
+                Err(_) => {
+                    warnings.push(Warning::InvalidStartupTimeout(value));
+                    Self::PROCESS_RUNNING_TIME_THRESHOLD
+                }
+            },
+            None => Self::PROCESS_RUNNING_TIME_THRESHOLD,
+        }
+    }
+
+    // An explicit --server-name wins over --server-name-template, --server-per-pane and
+    // $GVI_PROJECT, which lets each project shell (direnv, tmux, ...) have a gvim of its own, and
+    // all of them win over gvim's default name.
+    fn resolve_server_name(opts: &Options, env: impl Fn(&str) -> Option<String>) -> String {
+        opts.server_name
+            .clone()
+            .or_else(|| {
+                let template = opts.server_name_template.as_ref()?;
+                expand_server_name_template(template, &std::env::current_dir().ok()?)
+            })
+            .or_else(|| {
+                opts.server_per_pane
+                    .then(|| env("TMUX_PANE"))
+                    .flatten()
+                    .and_then(|pane| pane_server_name(&pane))
+            })
+            .or_else(|| env("GVI_PROJECT").filter(|name| !name.is_empty()))
+            .unwrap_or_else(|| Self::GVIM_SERVER_NAME.to_string())
+    }
+
+    fn instance(
+        &mut self,
+        paths: &[PathBuf],
+        opts: &Options,
+        explanation: &mut Vec<String>,
+    ) -> Instance {
+        // A single file with --fast would be fine in a fresh instance anyway, so don't bother
+        // scanning the process table for it.
+        if opts.fast && !opts.remote_only && paths.len() == 1 && paths[0].is_file() {
+            explanation
+                .push("A single file with --fast: not looking for a running gvim".to_string());
+            return Instance::Fresh;
+        }
+
+        // With detect_in_background, only the time spent waiting for it counts.
+        let started = self.clock.now();
+        let server = self.find_server(&self.server_name.clone());
+        self.detection += self.clock.now().duration_since(started);
+
+        match server {
+            // A registered server is ready to take remote commands regardless of its age.
+            Some(server) if self.supports_clientserver() => {
+                // The instance may have registered under another name than the one we asked
+                // for, and the remote calls have to use the real one.
+                self.server_name = server.name;
+
+                // One in --serverlist is ready however recently it started, and one of unknown
+                // age is taken to be up long enough.
+                let (running_time, age) = match server.running_time {
+                    _ if server.registered => (u64::MAX, "registered".to_string()),
+                    Some(running_time) => (running_time, format!("up for {}s", running_time)),
+                    None => (u64::MAX, "of unknown age".to_string()),
+                };
+                explanation.push(format!("Found server {}, {}", self.server_name, age));
+
+                Instance::Existing(running_time)
+            }
+            Some(_) => {
+                explanation.push(format!(
+                    "Found a gvim, but {} can't take remote commands",
+                    self.editor.display()
+                ));
+                Instance::Fresh
+            }
+            None => {
+                explanation.push(format!("No running gvim for server {}", self.server_name));
+                Instance::Fresh
+            }
+        }
+    }
+
+    // Without +clientserver the --remote-* options are silently ignored and the files never
+    // show up, so we have to know before trying to reuse an instance.
+    fn supports_clientserver(&mut self) -> bool {
+        if let Some(supported) = self.clientserver {
+            return supported;
+        }
+
+        // If we can't tell, keep trying the remote way as we always did.
+        let supported = self
+            .capabilities()
+            .is_none_or(|capabilities| capabilities.clientserver);
+        self.clientserver = Some(supported);
+
+        if !supported {
+            self.warn(Warning::NoClientserver(self.editor.clone()));
+        }
+
+        supported
+    }
+
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        if let Some(capabilities) = Capabilities::of_editor(&self.editor) {
+            return Some(capabilities);
+        }
+
+        let mtime = modified_time(&self.editor);
+
+        if let (Some(cache), Some(mtime)) = (&self.cache, mtime)
+            && let Some(capabilities) = cache.load(&self.editor, mtime)
+        {
+            return Some(capabilities);
+        }
+
+        let capabilities = self.probe_capabilities()?;
+
+        if let (Some(cache), Some(mtime)) = (&self.cache, mtime) {
+            // Not being able to cache only costs time.
+            let _ = cache.store(&self.editor, mtime, &capabilities);
+        }
+
+        Some(capabilities)
+    }
+
+    // gvim on Windows always comes with the client-server feature, and --version would pop up
+    // a message box there anyway.
+    #[cfg(target_os = "windows")]
+    fn probe_capabilities(&mut self) -> Option<Capabilities> {
+        None
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn probe_capabilities(&mut self) -> Option<Capabilities> {
+        let command = self.command(["--version"], [""; 0]);
+
+        match self.runner.output(command) {
+            Ok(output) if output.success => Some(Capabilities::from_version_output(&output.stdout)),
+            _ => None,
+        }
+    }
+
+    fn explain(&mut self, line: String) {
+        if let Some(explanation) = &mut self.explanation {
+            explanation.push(line);
+        }
+    }
+
+    // Reported along with the others at the end of the run, once per kind of trouble.
+    fn warn(&mut self, warning: Warning) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    // Whether the files go to a running instance, and which, along with how that was decided.
+    // Nothing is launched or opened to find out, but the detector asks gvim for --serverlist,
+    // and unless the capability cache knows already, --version, whose answer is then cached.
+    fn decide_instance(&mut self, paths: &[PathBuf], opts: &Options) -> (Instance, Vec<String>) {
+        let mut explanation = vec![format!(
+            "{} file(s) for server {}",
+            paths.len(),
+            self.server_name
+        )];
+
+        // A session brings its own layout, which only a fresh instance can take.
+        let instance = match opts.open_session {
+            Some(_) => {
+                explanation.push("--open-session takes a fresh instance".to_string());
+                Instance::Fresh
+            }
+            None => self.instance(paths, opts, &mut explanation),
+        };
+
+        explanation.push(match instance {
+            Instance::Existing(_) => "Reusing it through remote commands".to_string(),
+            Instance::Fresh if opts.remote_only => {
+                "--remote-only keeps gvi from launching a fresh instance".to_string()
+            }
+            Instance::Fresh => format!("Launching a fresh instance as {}", self.server_name),
+        });
+
+        (instance, explanation)
+    }
+
+    fn open(&mut self, normalized_paths: &[PathBuf], opts: &Options) -> Result<(), AppError> {
+        let (instance, explanation) = self.decide_instance(normalized_paths, opts);
+
+        for line in explanation {
+            self.explain(line);
+        }
+        self.open_in(normalized_paths, instance, opts)
+    }
+
+    // With --no-tab-for-existing-buffer, the files the server has loaded are left out of the
+    // usual opening and switched to afterwards, with :tab drop.
+    fn open_in(
+        &mut self,
+        normalized_paths: &[PathBuf],
+        instance: Instance,
+        opts: &Options,
+    ) -> Result<(), AppError> {
+        if !opts.no_tab_for_existing_buffer || opts.diff || instance == Instance::Fresh {
+            return self.open_files(normalized_paths, instance, opts);
+        }
+
+        let buffers = self.loaded_buffers()?;
+        let (loaded, new): (Vec<PathBuf>, Vec<PathBuf>) =
+            normalized_paths.iter().cloned().partition(|path| {
+                let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+                buffers.contains(&self.target_path(&path))
+            });
+
+        if loaded.is_empty() {
+            return self.open_files(normalized_paths, instance, opts);
+        }
+
+        self.open_files(&new, instance, opts)?;
+
+        let commands: Vec<String> = loaded
+            .iter()
+            .map(|path| {
+                let path = self.target_path(path).to_string_lossy().replace('\'', "''");
+                format!("exe 'tab drop ' . fnameescape('{}')", path)
+            })
+            .collect();
+        self.remote_send(&command_keys(&commands))?;
+        self.opened += loaded.len();
+
+        Ok(())
+    }
+
+    fn open_files(
+        &mut self,
+        normalized_paths: &[PathBuf],
+        instance: Instance,
+        opts: &Options,
+    ) -> Result<(), AppError> {
+        let layout = tab_groups(normalized_paths, opts);
+
+        // The files split side by side, the rest go to tab pages as usual.
+        let splits = match opts.vsplit_all && !opts.diff && layout.is_none() {
+            true => normalized_paths.len().min(MAX_SPLITS),
+            false => 0,
+        };
+        if normalized_paths.len() > splits && splits == MAX_SPLITS {
+            self.warn(Warning::TooManySplits(normalized_paths.len(), MAX_SPLITS));
+        }
+
+        let single = |paths: &[PathBuf]| -> Vec<OsString> {
+            paths
+                .iter()
+                .map(|path| self.target_path(path).into_os_string())
+                .collect()
+        };
+
+        // gvim -d compares the files of a single invocation, and gvim -O splits them.
+        let chunks = if opts.diff {
+            vec![single(normalized_paths)]
+        } else if layout.is_some() {
+            vec![]
+        } else if splits > 0 {
+            let mut chunks = vec![single(&normalized_paths[..splits])];
+            chunks.extend(self.batches(&normalized_paths[splits..], opts));
+            chunks
+        } else {
+            self.batches(normalized_paths, opts)
+        };
+
+        // Every file gets a tab page of the one window, unless each set goes to an instance of
+        // its own.
+        let tabs = opts.tab_warning.unwrap_or(TAB_WARNING);
+        let tab_count = match &layout {
+            Some((groups, _)) => groups.len(),
+            None => normalized_paths.len() - splits,
+        };
+        if tab_count > tabs && !opts.buffer && !opts.diff && self.clientserver != Some(false) {
+            self.warn(Warning::ManyTabs(tab_count, tabs));
+        }
+
+        if let Instance::Existing(running_time) = instance {
+            // Reuse a existing gvim instance.
+
+            // If no arguments have been supplied, there is nothing to do.
+            if normalized_paths.is_empty() {
+                return Ok(());
+            }
+
+            // Notice: just-launched gvim instance might have no remote functionalities yet.
+            // So for such cases we need to "wait" for a moment before the following execution.
+            // Not sure how long should we wait for but 3 seconds must be at most sufficient.
+            // (--startup-timeout changes that.)
+            let rest = self
+                .startup_timeout
+                .saturating_sub(Duration::from_secs(running_time));
+
+            if !rest.is_zero() {
+                self.explain(format!(
+                    "Waiting {} ms for it to take remote commands",
+                    rest.as_millis()
+                ));
+            }
+            if !self.dry {
+                self.clock.sleep(rest);
+            }
+
+            // These open their tab pages with :tabedit as well, after the current one.
+            let after_tab = match opts.into_tab {
+                Some(tab) if opts.diff || layout.is_some() || splits > 0 => self.go_to_tab(tab)?,
+                _ => String::new(),
+            };
+
+            match (&layout, opts.into_tab) {
+                _ if opts.diff => {
+                    let keys = diff_keys(normalized_paths, |path| self.target_path(path));
+                    self.remote_send(&(after_tab + &keys))?;
+                    self.opened += normalized_paths.len();
+                }
+                (Some((groups, split)), _) => {
+                    let commands =
+                        layout_commands(groups, *split, false, |path| self.target_path(path));
+                    self.remote_send(&(after_tab + &command_keys(&commands)))?;
+                    self.opened += normalized_paths.len();
+                }
+                _ if splits > 0 => {
+                    let keys =
+                        vsplit_keys(&normalized_paths[..splits], |path| self.target_path(path));
+                    self.remote_send(&(after_tab + &keys))?;
+                    self.opened += splits;
+
+                    let rest = chunks.into_iter().skip(1).collect();
+                    self.dispatch(&self.reuse_instance_options(opts), rest, opts, true)?;
+                }
+                (_, Some(tab)) => self.open_into_tab(normalized_paths, tab, opts)?,
+                (_, None) => {
+                    self.dispatch(&self.reuse_instance_options(opts), chunks, opts, true)?
+                }
+            }
+
+            if !opts.commands.is_empty() {
+                self.remote_send(&command_keys(&opts.commands))?;
+            }
+
+            // Whether --remote-tab raises the window depends on the platform and the window
+            // manager, but gvim can always be asked to come forward itself.
+
+            if opts.focus && self.dry {
+                self.explain("Would ask it to come forward".to_string());
+            } else if opts.focus {
+                tolerate_remote_failure(self.remote_expr("foreground()"))?;
+            }
+
+            self.record_session(opts)
+        } else if opts.remote_only {
+            Err(AppError::NoRunningServer(self.server_name.clone()))
+        } else {
+            // Create a new gvim instance.
+            let mut chunks = chunks.into_iter();
+            let mut options = self.fresh_instance_options_for(opts);
+
+            if opts.diff {
+                options.push("-d".to_string());
+            } else if splits > 0 {
+                options.push("-O".to_string());
+            }
+
+            // Restoring the session would replace the files, so they all follow it.
+            let first = match (&opts.open_session, &layout) {
+                (Some(session), _) => {
+                    options.extend(["-S".to_string(), session.to_string_lossy().into_owned()]);
+                    vec![]
+                }
+                // The layout comes first, so that --cmd runs in it.
+                (None, Some((groups, split))) => {
+                    let commands =
+                        layout_commands(groups, *split, true, |path| self.target_path(path));
+                    let at = self.fresh_instance_options().len();
+                    options.splice(at..at, ["-c".to_string(), commands.join(" | ")]);
+                    self.opened += normalized_paths.len();
+                    vec![]
+                }
+                (None, None) => chunks.next().unwrap_or_default(),
+            };
+
+            self.dispatch(&options, vec![first], opts, false)?;
+
+            // Anything that didn't fit goes to the instance we've just launched, once it is able
+            // to accept remote commands.
+            let rest: Vec<Vec<OsString>> = chunks.collect();
+
+            if rest.is_empty() && opts.record_session.is_none() {
+                return Ok(());
+            }
+
+            if !self.supports_clientserver() {
+                if opts.record_session.is_some() {
+                    self.warn(Warning::NoSessionRecorded);
+                }
+
+                return self.dispatch(&self.fresh_instance_options_for(opts), rest, opts, false);
+            }
+
+            self.wait_until_registered();
+            self.dispatch(&self.reuse_instance_options(opts), rest, opts, true)?;
+            self.record_session(opts)
+        }
+    }
+
+    // Rather than give a fresh instance the time the slowest one could take to register its
+    // server, ask gvim for the servers until it shows up. It may never do (say, without an X
+    // server), so the remote commands are tried after that time anyway.
+    fn wait_until_registered(&mut self) {
+        self.explain(format!(
+            "Waiting up to {} ms for {} to register",
+            self.startup_timeout.as_millis(),
+            self.server_name
+        ));
+        if self.dry {
+            return;
+        }
+
+        let deadline = self.clock.now() + self.startup_timeout;
+
+        loop {
+            let command = self.command(["--serverlist"], [""; 0]);
+
+            if let Ok(output) = self.runner.output(command)
+                && detect::parse_serverlist(&output.stdout)
+                    .iter()
+                    .any(|server| server.eq_ignore_ascii_case(&self.server_name))
+            {
+                return;
+            }
+
+            if self.clock.now() >= deadline {
+                return;
+            }
+
+            self.clock.sleep(Self::REGISTRATION_POLL_INTERVAL);
+        }
+    }
+
+    // With --max-concurrent-spawns, wait until few enough of the instances launched before are
+    // still starting up. One is done once its server shows up, or after the time the slowest
+    // could take to register, which is all there is to go by without +clientserver.
+    fn throttle_spawns(&mut self, opts: &Options) {
+        let Some(max) = opts.max_concurrent_spawns else {
+            return;
+        };
+
+        loop {
+            let threshold = self.startup_timeout;
+            let now = self.clock.now();
+            self.launching
+                .retain(|(_, launched)| now.duration_since(*launched) < threshold);
+
+            if self.launching.len() < max {
+                return;
+            }
+
+            if self.supports_clientserver() {
+                let command = self.command(["--serverlist"], [""; 0]);
+
+                if let Ok(output) = self.runner.output(command) {
+                    let servers = detect::parse_serverlist(&output.stdout);
+                    self.launching.retain(|(name, _)| {
+                        !servers
+                            .iter()
+                            .any(|server| server.eq_ignore_ascii_case(name))
+                    });
+                }
+            }
+
+            if self.launching.len() < max {
+                return;
+            }
+
+            self.clock.sleep(Self::REGISTRATION_POLL_INTERVAL);
+        }
+    }
+
+    // Only the server knows its layout, so it is the one to write the session file. gvim may
+    // run in another directory, hence the absolute path.
+    fn record_session(&mut self, opts: &Options) -> Result<(), AppError> {
+        let Some(path) = &opts.record_session else {
+            return Ok(());
+        };
+
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        let keys = format!(
+            "<C-\\><C-N>:exe 'mksession! ' . fnameescape({})<CR>",
+            vim_string_keys(&self.target_path(&path))
+        );
+
+        self.remote_send(&keys)
+    }
+
+    // One gvim invocation per batch. An interrupt stops the ones not launched yet, but leaves
+    // the instances already running alone.
+    fn dispatch(
+        &mut self,
+        options: &[String],
+        batches: Vec<Vec<OsString>>,
+        opts: &Options,
+        remote: bool,
+    ) -> Result<(), AppError> {
+        let delay = Duration::from_millis(opts.open_delay_ms.unwrap_or(0));
+
+        for (i, batch) in batches.into_iter().enumerate() {
+            if self.interrupted.load(Ordering::SeqCst) {
+                return Err(AppError::Interrupted(self.opened));
+            }
+
+            if remote && i > 0 && !delay.is_zero() && !self.dry {
+                self.clock.sleep(delay);
+            }
+
+            let files: Vec<PathBuf> = batch
+                .iter()
+                .filter(|arg| !arg.to_string_lossy().starts_with('+'))
+                .map(PathBuf::from)
+                .collect();
+
+            if !remote {
+                self.throttle_spawns(opts);
+            }
+
+            // An existing instance is sent the search instead, once the file is open.
+            let search = files.first().and_then(|file| opts.searches.get(file));
+            let batch: Vec<OsString> = match search {
+                Some(_) if remote => batch
+                    .into_iter()
+                    .filter(|arg| !arg.to_string_lossy().starts_with("+/"))
+                    .collect(),
+                _ => batch,
+            };
+
+            if !remote && opts.background {
+                let command = self.background_command(options, batch);
+                self.spawn(command)?;
+            } else {
+                self.exec_gvim(options, batch)?;
+            }
+            self.opened += files.len();
+
+            if !remote && opts.max_concurrent_spawns.is_some() {
+                self.launching
+                    .push((self.server_name.clone(), self.clock.now()));
+            }
+
+            if remote
+                && let Some(pattern) = search
+                && !opts.lines.contains_key(&files[0])
+            {
+                self.remote_send(&search_keys(pattern))?;
+            }
+
+            // --remote-tab has no room for ++enc, so reload the files the server has just opened.
+            if remote && let Some(encoding) = &opts.encoding {
+                self.remote_send(&reload_with_encoding_keys(&files, encoding, opts.buffer))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // --remote-tab always appends the tab pages, so tell the server where to put them instead.
+    fn open_into_tab(
+        &mut self,
+        paths: &[PathBuf],
+        tab: usize,
+        opts: &Options,
+    ) -> Result<(), AppError> {
+        self.check_tab(tab)?;
+
+        // Only the first chunk goes to the tab page, the rest follow the files opened before.
+        let mut tab = Some(tab);
+
+        for chunk in Self::chunks(paths, self.arg_budget) {
+            if self.interrupted.load(Ordering::SeqCst) {
+                return Err(AppError::Interrupted(self.opened));
+            }
+
+            let keys = tabedit_keys(chunk, tab.take(), opts, |path| self.target_path(path));
+            self.remote_send(&keys)?;
+            self.opened += chunk.len();
+        }
+
+        Ok(())
+    }
+
+    fn check_tab(&mut self, tab: usize) -> Result<(), AppError> {
+        // An old or busy server may not answer, which is no reason not to try.
+        let tabs = tolerate_remote_failure(self.remote_expr("tabpagenr('$')"))?;
+
+        match tabs.trim().parse::<usize>() {
+            Ok(tabs) if tab > tabs => Err(AppError::InvalidOptionValue(
+                "--into-tab".to_string(),
+                tab.to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Keys making tab page `tab` the current one, once it's known to exist.
+    fn go_to_tab(&mut self, tab: usize) -> Result<String, AppError> {
+        self.check_tab(tab)?;
+
+        Ok(format!("<C-\\><C-N>:tabnext {}<CR>", tab))
+    }
+
+    // Arguments of each gvim invocation. A file to be opened at a certain line or match (or with
+    // a certain filetype) gets an invocation of its own, since gvim applies +{command} to the
+    // first file only.
+    fn batches(&self, paths: &[PathBuf], opts: &Options) -> Vec<Vec<OsString>> {
+        let mut batches = vec![];
+        let mut start = 0;
+        let flush = |run: &[PathBuf], batches: &mut Vec<Vec<OsString>>| {
+            for chunk in Self::chunks(run, self.arg_budget) {
+                batches.push(
+                    chunk
+                        .iter()
+                        .map(|p| self.target_path(p).into_os_string())
+                        .collect(),
+                );
+            }
+        };
+
+        for (i, path) in paths.iter().enumerate() {
+            let command = match (
+                opts.lines.get(path),
+                opts.searches.get(path),
+                opts.filetypes.get(path),
+            ) {
+                (Some(line), _, _) => format!("+{}", line),
+                (None, Some(pattern), _) => format!("+/{}", search_pattern(pattern)),
+                (None, None, Some(filetype)) => format!("+setf {}", filetype),
+                (None, None, None) => continue,
+            };
+
+            flush(&paths[start..i], &mut batches);
+            batches.push(vec![
+                OsString::from(command),
+                self.target_path(path).into_os_string(),
+            ]);
+            start = i + 1;
+        }
+
+        flush(&paths[start..], &mut batches);
+
+        batches
+    }
+
+    // The path as the editor sees it, which is another one for a Windows gvim under WSL, or for
+    // a long path on Windows.
+    fn target_path(&self, path: &Path) -> PathBuf {
+        match self.translate {
+            Some(translate) => translate(path),
+            #[cfg(windows)]
+            None => extended_length_path(path),
+            #[cfg(not(windows))]
+            None => path.to_path_buf(),
+        }
+    }
+
+    // Split paths into runs whose total length stays within the budget, so that a single gvim
+    // invocation never exceeds the OS limit of the command line length.
+    fn chunks(paths: &[PathBuf], budget: usize) -> Vec<&[PathBuf]> {
+        let mut chunks = vec![];
+        let mut start = 0;
+        let mut length = 0;
+
+        for (i, path) in paths.iter().enumerate() {
+            // +1 for the separator (or the terminating NUL).
+            let len = path.as_os_str().len() + 1;
+
+            if i > start && length + len > budget {
+                chunks.push(&paths[start..i]);
+                start = i;
+                length = 0;
+            }
+
+            length += len;
+        }
+
+        if start < paths.len() {
+            chunks.push(&paths[start..]);
+        }
+
+        chunks
+    }
+
+    // What the server evaluates the expression to, without the line break gvim adds. Errors come
+    // on stderr (with a failing exit status, mostly). A server that has only just been launched
+    // may not be registered yet, so that one is tried a few more times.
+    fn remote_expr(&mut self, expr: &str) -> Result<String, AppError> {
+        let mut attempts = Self::REMOTE_EXPR_ATTEMPTS;
+
+        loop {
+            let command =
+                self.command(["--servername", &self.server_name, "--remote-expr"], [expr]);
+            let output = self
+                .runner
+                .output(command)
+                .map_err(AppError::CommandSpawnError)?;
+
+            let error = vim_error(&output.stderr)
+                .or_else(|| (!output.success).then(|| output.stderr.trim().to_string()));
+
+            let Some(message) = error else {
+                let result = output.stdout.strip_suffix('\n').unwrap_or(&output.stdout);
+                return Ok(result.strip_suffix('\r').unwrap_or(result).to_string());
+            };
+
+            attempts -= 1;
+            if attempts == 0 || !message.starts_with("E247:") {
+                return Err(AppError::RemoteExprFailed(
+                    self.server_name.clone(),
+                    expr.to_string(),
+                    message,
+                ));
+            }
+
+            self.clock.sleep(Self::REGISTRATION_POLL_INTERVAL);
+        }
+    }
+
+    // Start looking for the server on a thread of its own, for the process scan and --serverlist
+    // to overlap with the expansion of the directories.
+    fn detect_in_background(&mut self) {
+        let detector = std::mem::replace(&mut self.detector, Box::new(LentDetector));
+        let name = self.server_name.clone();
+        let thread = std::thread::spawn({
+            let name = name.clone();
+            move || {
+                let server = detector.find_server(&name);
+                (detector, server)
+            }
+        });
+
+        self.pending = Some((name, thread));
+    }
+
+    // The result of detect_in_background, once it's done, if it looked for the same server.
+    fn find_server(&mut self, name: &str) -> Option<ServerInfo> {
+        if let Some((pending, thread)) = self.pending.take() {
+            let (detector, server) = thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            self.detector = detector;
+
+            if pending == name {
+                return server;
+            }
+        }
+
+        self.detector.find_server(name)
+    }
+
+    // Whether there is a server to ask, which is then the one we talk to.
+    fn reachable_server(&mut self) -> bool {
+        let Some(server) = self.find_server(&self.server_name.clone()) else {
+            return false;
+        };
+
+        if !self.supports_clientserver() {
+            return false;
+        }
+
+        self.server_name = server.name;
+        true
+    }
+
+    // The files loaded in the server, none if there is no server to ask.
+    fn opened_files(&mut self) -> Result<Vec<PathBuf>, AppError> {
+        if !self.reachable_server() {
+            return Ok(vec![]);
+        }
+
+        self.loaded_buffers()
+    }
+
+    fn loaded_buffers(&mut self) -> Result<Vec<PathBuf>, AppError> {
+        let listing = self.remote_expr(Self::LIST_BUFFERS_EXPR)?;
+
+        Ok(listing
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    // The working directory of the server, if there is one to ask and it answers.
+    fn server_cwd(&mut self) -> Result<Option<PathBuf>, AppError> {
+        if !self.reachable_server() {
+            return Ok(None);
+        }
+
+        let cwd = tolerate_remote_failure(self.remote_expr("getcwd()"))?;
+
+        Ok(Some(PathBuf::from(cwd)).filter(|cwd| !cwd.as_os_str().is_empty()))
+    }
+
+    fn remote_send(&mut self, keys: &str) -> Result<(), AppError> {
+        let options = ["--servername", &self.server_name, "--remote-send"].map(String::from);
+
+        self.exec_gvim(options, [keys])
+    }
+
+    // Launch a server of the name, unless it is running already, that outlives gvi and the
+    // terminal it was launched from.
+    fn keep_alive(&mut self) -> Result<(), AppError> {
+        if self.find_server(&self.server_name.clone()).is_some() {
+            return Ok(());
+        }
+
+        let command = self.detached_command(["--servername", &self.server_name]);
+        self.runner
+            .spawn(command)
+            .map_err(AppError::CommandSpawnError)
+    }
+
+    // In a session of its own, the editor gets no SIGHUP when the terminal goes away, and with
+    // nothing of the terminal left open it doesn't hold on to it either.
+    #[cfg(unix)]
+    fn detached_command<I, S>(&self, options: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        use std::os::unix::process::CommandExt;
+        use std::process::Stdio;
+
+        let mut command = self.command(options, [""; 0]);
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+
+        // SAFETY: setsid is async-signal-safe, and nothing else runs between fork and exec.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        command
+    }
+
+    // Without a console, and out of the process group of the one gvi runs in.
+    #[cfg(target_os = "windows")]
+    fn detached_command<I, S>(&self, options: I) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        use std::os::windows::process::CommandExt;
+
+        let mut command = self.command(options, [""; 0]);
+        command.creation_flags(Self::DETACHED_PROCESS | Self::CREATE_NEW_PROCESS_GROUP);
+
+        command
+    }
+
+    // Quit the named server, which has to exist: gvim would launch one just to quit it otherwise.
+    fn close(&mut self, name: &str, force: bool) -> Result<(), AppError> {
+        let server = self
+            .find_server(name)
+            .ok_or_else(|| AppError::ServerNotFound(name.to_string()))?;
+
+        self.server_name = server.name;
+
+        if force {
+            self.remote_send("<C-\\><C-N>:qall!<CR>")
+        } else {
+            self.remote_send("<C-\\><C-N>:qall<CR>")
+        }
+    }
+
+    // remote_startserver() is the only way to name a server, but Vim lets an instance register
+    // only once and answers E941 after that. Ask anyway, and say so when it refuses.
+    fn rename_server(&mut self, old: &str, new: &str) -> Result<(), AppError> {
+        let server = self
+            .find_server(old)
+            .ok_or_else(|| AppError::ServerNotFound(old.to_string()))?;
+
+        self.server_name = server.name;
+
+        let expr = format!("remote_startserver('{}')", new.replace('\'', "''"));
+
+        match self.remote_expr(&expr) {
+            Err(AppError::RemoteExprFailed(..)) => Err(AppError::RenameRefused(
+                self.server_name.clone(),
+                new.to_string(),
+            )),
+            result => result.map(|_| ()),
+        }
+    }
+
+    // Ask the server to reload the watched files if any of them has changed on disk.
+    fn reload_if_changed(
+        &mut self,
+        watcher: &mut Watcher,
+        mtime: impl Fn(&Path) -> Option<SystemTime>,
+    ) -> Result<bool, AppError> {
+        if !watcher.changed(mtime) {
+            return Ok(false);
+        }
+
+        self.remote_send(Watcher::RELOAD_KEYS)?;
+
+        Ok(true)
+    }
+
+    // gvim registers itself as "GVIM" unless told otherwise.
+    fn fresh_instance_options(&self) -> Vec<String> {
+        if self.server_name == Self::GVIM_SERVER_NAME {
+            vec![]
+        } else {
+            vec!["--servername".to_string(), self.server_name.clone()]
+        }
+    }
+
+    fn fresh_instance_options_for(&self, opts: &Options) -> Vec<String> {
+        let mut options = self.fresh_instance_options();
+
+        // Reload every file with the encoding, then go back to the first one.
+        if let Some(encoding) = &opts.encoding {
+            options.extend([
+                "-c".to_string(),
+                format!("argdo e ++enc={}", encoding),
+                "-c".to_string(),
+                "first".to_string(),
+            ]);
+        }
+
+        for command in &opts.commands {
+            options.extend(["-c".to_string(), command.clone()]);
+        }
+
+        options
+    }
+
+    fn reuse_instance_options(&self, opts: &Options) -> [String; 3] {
+        // --remote only adds the files to the buffer (and argument) list, leaving the current
+        // window as it is, whereas --remote-tab opens a tab page for each of them.
+        let remote = if opts.buffer {
+            "--remote"
+        } else {
+            "--remote-tab"
+        };
+
+        ["--servername", &self.server_name, remote].map(String::from)
+    }
+
+    fn exec_gvim<I, S, T, U>(&mut self, options: I, args: T) -> Result<(), AppError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+        T: IntoIterator<Item = U>,
+        U: AsRef<std::ffi::OsStr>,
+    {
+        let command = self.command(options, args);
+
+        self.spawn(command)
+    }
+
+    // Each gvim invocation of opening the files goes through here, for --explain to tell.
+    fn spawn(&mut self, command: Command) -> Result<(), AppError> {
+        if self.explanation.is_some() {
+            let verb = if self.dry { "Would run" } else { "Running" };
+            self.explain(format!("{}: {}", verb, command_line(&command)));
+        }
+
+        if self.dry {
+            return Ok(());
+        }
+
+        self.runner
+            .spawn(command)
+            .map_err(AppError::CommandSpawnError)
+    }
+
+    // `open -g` launches an application without bringing it to the front. MacVim's command line
+    // tools live inside the application bundle, which `open` wants instead.
+    #[cfg(target_os = "macos")]
+    fn background_command(&mut self, options: &[String], args: Vec<OsString>) -> Command {
+        let app = self
+            .editor
+            .ancestors()
+            .find(|dir| dir.extension().is_some_and(|ext| ext == "app"))
+            .map_or_else(|| PathBuf::from("MacVim"), Path::to_path_buf);
+
+        let mut command = Command::new("open");
+        command
+            .args(["-g", "-n", "-a"])
+            .arg(app)
+            .arg("--args")
+            .args(options)
+            .args(args);
+
+        command
+    }
+
+    // `start /MIN` shows the new window minimized, which leaves the focus where it is.
+    #[cfg(target_os = "windows")]
+    fn background_command(&mut self, options: &[String], args: Vec<OsString>) -> Command {
+        let mut command = Command::new("cmd");
+        command
+            .args(["/C", "start", "\"\"", "/MIN"])
+            .arg(&self.editor)
+            .args(options)
+            .args(args);
+
+        {
+            use std::os::windows::process::CommandExt;
+            command.creation_flags(Self::DETACHED_PROCESS);
+        }
+
+        command
+    }
+
+    // Whether a new window gets the focus is up to the window manager here.
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    fn background_command(&mut self, options: &[String], args: Vec<OsString>) -> Command {
+        self.warn(Warning::BackgroundUnsupported);
+        self.command(options, args)
+    }
+
+    fn command<I, S, T, U>(&self, options: I, args: T) -> Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+        T: IntoIterator<Item = U>,
+        U: AsRef<std::ffi::OsStr>,
+    {
+        let mut command = Command::new(&self.editor);
+        command.args(options).args(args);
+
+        #[cfg(target_os = "windows")]
+        {
+            use std::os::windows::process::CommandExt;
+            command.creation_flags(Self::DETACHED_PROCESS);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            command.env("GDK_BACKEND", "x11");
+        }
+
+        command
+    }
+}
+
+impl Opener for Gvim {
+    fn open(&mut self, files: &[PathBuf], opts: &Options) -> Result<(), AppError> {
+        Gvim::open(self, files, opts)
+    }
+}
+
+// Keeps track of the modification time of each watched file. Polled rather than told by inotify,
+// FSEvents and the like: the files are few (MAX_FILES at most), a stat of each twice a second costs
+// next to nothing, and it works the same on every platform and file system, NFS and WSL's /mnt
+// included, without a dependency for it.
+struct Watcher {
+    mtimes: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl Watcher {
+    const INTERVAL: Duration = Duration::from_millis(500);
+    // :checktime reloads unmodified buffers when 'autoread' is set and asks otherwise, so
+    // unsaved changes are never thrown away the way :e! would.
+    const RELOAD_KEYS: &str = "<C-\\><C-N>:checktime<CR>";
+
+    fn new(files: &[PathBuf], mtime: impl Fn(&Path) -> Option<SystemTime>) -> Self {
+        Watcher {
+            mtimes: files.iter().map(|f| (f.clone(), mtime(f))).collect(),
+        }
+    }
+
+    fn changed(&mut self, mtime: impl Fn(&Path) -> Option<SystemTime>) -> bool {
+        let mut changed = false;
+
+        for (path, last) in self.mtimes.iter_mut() {
+            let current = mtime(path);
+
+            if current != *last {
+                *last = current;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum ColorChoice {
+    // Only when stderr is a terminal and NO_COLOR isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+        }
+    }
+
+    // See https://no-color.org: a non-empty NO_COLOR turns the colors off.
+    fn enabled(self, env: impl Fn(&str) -> Option<String>, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                is_terminal && env("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+    // Nothing wrong, just worth knowing about.
+    Note,
+}
+
+// Whether messages on stderr are colored. Plain until the options say otherwise.
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+fn format_message(severity: Severity, message: &str, color: bool) -> String {
+    let (label, code) = match severity {
+        Severity::Error => ("Error", "31"),
+        Severity::Warning => ("Warning", "33"),
+        Severity::Note => ("Note", "36"),
+    };
+
+    if color {
+        format!("\x1b[{}m{}:\x1b[0m {}", code, label, message)
+    } else {
+        format!("{}: {}", label, message)
+    }
+}
+
+fn report(severity: Severity, message: &str) {
+    eprintln!(
+        "{}",
+        format_message(severity, message, COLOR.load(Ordering::SeqCst))
+    );
+}
+
+// Something gvi went on despite, reported at the end of the run rather than in between.
+#[derive(Clone, Debug, PartialEq)]
+enum Warning {
+    // The editor can't be reused, so every file set gets a fresh instance.
+    NoClientserver(PathBuf),
+    NoSessionRecorded,
+    BackgroundUnsupported,
+    // $GVI_STARTUP_TIMEOUT_MS, which isn't a number of milliseconds.
+    InvalidStartupTimeout(String),
+    // $GVI_LAYOUT, which isn't a layout.
+    InvalidLayout(String),
+    // More arguments than --max-args, of which only that many are kept.
+    TruncatedArguments(usize, usize),
+    // An argument that doesn't exist.
+    MissingArgument(PathBuf),
+    // The members of a {start..end} range that don't exist: how many, and the first of them.
+    MissingRangeMembers {
+        range: PathBuf,
+        missing: usize,
+        first: PathBuf,
+    },
+    // More files than --vsplit-all splits a window into, and how many it does.
+    TooManySplits(usize, usize),
+    // The number of tab pages about to open in one window, more than --warn-tabs.
+    ManyTabs(usize, usize),
+    // A file which is no longer one by the time it would be opened.
+    GoneBeforeOpening(PathBuf),
+    // A compressed file --decompress couldn't make a copy of, opened as it is.
+    NotDecompressed(PathBuf),
+    // A file whose name is longer than --max-name-length, which is given second.
+    LongName(PathBuf, usize),
+    // The files left out once the ones before them came to --until-size.
+    OverSizeBudget {
+        skipped: Vec<PathBuf>,
+        budget: u64,
+    },
+    // A file over --max-size-per-file, and whether it was opened anyway.
+    Oversized {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+        opened: bool,
+    },
+}
+
+impl Warning {
+    // The order of the groups in the report.
+    fn kind(&self) -> usize {
+        match self {
+            Warning::NoClientserver(_) => 0,
+            Warning::NoSessionRecorded => 1,
+            Warning::BackgroundUnsupported => 2,
+            Warning::InvalidStartupTimeout(_) => 3,
+            Warning::InvalidLayout(_) => 4,
+            Warning::TruncatedArguments(..) => 5,
+            Warning::MissingArgument(_) => 6,
+            Warning::MissingRangeMembers { .. } => 7,
+            Warning::TooManySplits(..) => 8,
+            Warning::ManyTabs(..) => 9,
+            Warning::OverSizeBudget { .. } => 10,
+            Warning::LongName(..) => 11,
+            Warning::Oversized { .. } => 12,
+            Warning::GoneBeforeOpening(_) => 13,
+            Warning::NotDecompressed(_) => 14,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::NoClientserver(editor) => write!(
+                f,
+                "{} has no +clientserver, so a new instance is opened every time.",
+                editor.display()
+            ),
+            Warning::NoSessionRecorded => write!(
+                f,
+                "--record-session needs a gvim with +clientserver, no session is recorded"
+            ),
+            Warning::BackgroundUnsupported => {
+                write!(f, "--background is not supported on this platform.")
+            }
+            Warning::InvalidStartupTimeout(value) => write!(
+                f,
+                "GVI_STARTUP_TIMEOUT_MS is '{}', not a number of milliseconds, so it is ignored.",
+                value
+            ),
+            Warning::InvalidLayout(value) => write!(
+                f,
+                "GVI_LAYOUT is '{}', which is not a layout, so each file gets a tab page.",
+                value
+            ),
+            Warning::TruncatedArguments(count, limit) => write!(
+                f,
+                "There are {} arguments, more than {}, so only the first {} are opened.",
+                count, limit, limit
+            ),
+            Warning::MissingArgument(path) => {
+                write!(f, "{} does not exist, so it is left out.", path.display())
+            }
+            Warning::MissingRangeMembers {
+                range,
+                missing: 1,
+                first,
+            } => write!(
+                f,
+                "{} does not exist, so {} comes to one file less.",
+                first.display(),
+                range.display()
+            ),
+            Warning::MissingRangeMembers {
+                range,
+                missing,
+                first,
+            } => write!(
+                f,
+                "{} members of {} do not exist, {} the first of them.",
+                missing,
+                range.display(),
+                first.display()
+            ),
+            Warning::TooManySplits(files, splits) => write!(
+                f,
+                "{} files are too many to split a window into, only the first {} are split; the others get tab pages.",
+                files, splits
+            ),
+            Warning::GoneBeforeOpening(path) => write!(
+                f,
+                "{} is gone or no longer a file, so it is left out.",
+                path.display()
+            ),
+            Warning::NotDecompressed(path) => write!(
+                f,
+                "{} couldn't be decompressed, so it is opened as it is.",
+                path.display()
+            ),
+            Warning::LongName(path, limit) => write!(
+                f,
+                "{} has a name longer than {} characters, so it is left out.",
+                path.display(),
+                limit
+            ),
+            Warning::OverSizeBudget { skipped, budget } => {
+                write!(
+                    f,
+                    "The files before these come to {} bytes or less, so these are not opened:",
+                    budget
+                )?;
+                for path in skipped {
+                    write!(f, "\n  {}", path.display())?;
+                }
+                Ok(())
+            }
+            Warning::ManyTabs(tabs, limit) => write!(
+                f,
+                "{} tab pages are about to open in one window (more than {}); --buffer opens the files without them.",
+                tabs, limit
+            ),
+            Warning::Oversized {
+                path,
+                size,
+                limit,
+                opened,
+            } => write!(
+                f,
+                "{} is {} bytes, more than {} per file, {}.",
+                path.display(),
+                size,
+                limit,
+                if *opened {
+                    "opened anyway as an argument"
+                } else {
+                    "skipped"
+                }
+            ),
+        }
+    }
+}
+
+// WSL sets WSL_DISTRO_NAME for its shells, and both WSL 1 and 2 name their kernels after
+// Microsoft.
+fn is_wsl(env: impl Fn(&str) -> Option<String>, osrelease: Option<&str>) -> bool {
+    env("WSL_DISTRO_NAME").is_some_and(|name| !name.is_empty())
+        || osrelease.is_some_and(|release| release.to_lowercase().contains("microsoft"))
+}
+
+// Unless told, take the Windows gvim only under WSL, and only if there is no Linux gvim (through
+// WSLg, say) or explicitly given editor to take instead.
+fn wants_windows_gvim(opts: &Options, under_wsl: bool, linux_gvim: impl Fn() -> bool) -> bool {
+    opts.wsl
+        .unwrap_or_else(|| under_wsl && opts.editor_path.is_none() && !linux_gvim())
+}
+
+// The gvim.exe processes run on the Windows side, where a scan of ours doesn't reach, so under WSL
+// only the server list tells whether there is one.
+fn wsl_detector(editor: PathBuf) -> Box<dyn InstanceDetector> {
+    Box::new(ServerListDetector { editor })
+}
+
+// A Windows program can't make sense of /home/..., so ask WSL for the path it knows the file by.
+fn windows_path(path: &Path) -> PathBuf {
+    match Command::new("wslpath").arg("-w").arg(path).output() {
+        Ok(output) if output.status.success() => PathBuf::from(extended_length(
+            String::from_utf8_lossy(&output.stdout).trim_end(),
+        )),
+        _ => path.to_path_buf(),
+    }
+}
+
+// Beyond MAX_PATH characters, Windows only takes a path in the \\?\ form, which gvim otherwise
+// handles poorly (see existing_arguments), so shorter paths are left alone. The form turns off
+// all normalization, hence the backslashes, and UNC shares have one of their own.
+const MAX_PATH: usize = 260;
+
+fn extended_length(absolute: &str) -> String {
+    if absolute.len() < MAX_PATH || absolute.starts_with(r"\\?\") || absolute.starts_with(r"\\.\") {
+        return absolute.to_string();
+    }
+
+    let absolute = absolute.replace('/', "\\");
+
+    match absolute.strip_prefix(r"\\") {
+        Some(share) => format!(r"\\?\UNC\{}", share),
+        None => format!(r"\\?\{}", absolute),
+    }
+}
+
+#[cfg(windows)]
+fn extended_length_path(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+
+    if absolute.as_os_str().len() < MAX_PATH {
+        return path.to_path_buf();
+    }
+
+    PathBuf::from(extended_length(&absolute.to_string_lossy()))
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    // SAFETY: the handler does nothing but store to an atomic, which is async-signal-safe.
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as *const () as libc::sighandler_t,
+        );
+    }
+}
+
+// Ctrl-C keeps terminating us right away elsewhere.
+#[cfg(not(unix))]
+fn install_interrupt_handler() {}
+
+// A file of our own in a directory others can write to, such as /tmp: a name somebody took first,
+// or planted a symlink at, is passed over for the next one. Only we can read it on unix.
+fn create_temp_file(
+    dir: &Path,
+    name: impl Fn(usize) -> String,
+) -> std::io::Result<(PathBuf, std::fs::File)> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut attempt = 0;
+    loop {
+        let path = dir.join(name(attempt));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+// Windows has no executable bit; whatever exists as a file is good enough here.
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// File system access while collecting files, so that tests can watch it.
+trait FileSystem {
+    fn metadata(&self, path: &Path) -> std::io::Result<std::fs::Metadata>;
+}
+
+struct RealFs;
+
+impl FileSystem for RealFs {
+    fn metadata(&self, path: &Path) -> std::io::Result<std::fs::Metadata> {
+        std::fs::metadata(path)
+    }
+}
+
+struct App {
+    args: Vec<String>,
+    fs: Box<dyn FileSystem>,
+    gvim: Gvim,
+    config: Config,
+    // Takes the place of gvim, see --opener.
+    opener: Option<Box<dyn Opener>>,
+    opts: Options,
+    files: Vec<PathBuf>,
+    // Total size of the files, added up while collecting them.
+    size: Option<u64>,
+    // The copies --decompress made this run, removed again if gvim doesn't get to them.
+    copies: Vec<PathBuf>,
+    counts: ExpansionCounts,
+    // Those of expanding the arguments; gvim keeps its own until the end.
+    warnings: Vec<Warning>,
+    // How long each phase took, in order, for --profile.
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl App {
+    fn new() -> App {
+        App {
+            args: std::env::args().collect(),
+            fs: Box::new(RealFs),
+            gvim: Gvim::new(),
+            config: Config::default(),
+            opener: None,
+            opts: Options::default(),
+            files: vec![],
+            size: None,
+            copies: vec![],
+            counts: ExpansionCounts::default(),
+            warnings: vec![],
+            phases: vec![],
+        }
+    }
+
+    fn print_profile(&self, output: &mut impl std::io::Write) {
+        for (phase, duration) in &self.phases {
+            let _ = writeln!(
+                output,
+                "profile: {:<10} {:>8.3} ms",
+                phase,
+                duration.as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    // All the warnings of the run so far, grouped by kind and otherwise in the order they came
+    // up.
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.append(&mut self.gvim.warnings);
+        warnings.sort_by_key(Warning::kind);
+        warnings
+    }
+
+    fn report_warnings(&mut self) {
+        let warnings = self.take_warnings();
+
+        if !self.opts.quiet {
+            for warning in warnings {
+                report(Severity::Warning, &warning.to_string());
+            }
+        }
+    }
+
+    // For the limits: nothing is opened, but what came up on the way is still worth knowing.
+    fn fail(&mut self, message: String) -> ! {
+        self.remove_copies();
+        self.report_warnings();
+        report(Severity::Error, &message);
+        std::process::exit(1);
+    }
+
+    // With --relative-to-server, relative arguments are taken from the server's working directory,
+    // along with the lines and searches that go with them. Without a server to ask, from ours.
+    // That is also where the file of a file:/pattern argument has to be found.
+    fn resolve_against_server(&mut self, items: Vec<PathBuf>) -> Result<Vec<PathBuf>, AppError> {
+        let cwd = match self.opts.relative_to_server {
+            true => self.gvim.server_cwd()?,
+            false => None,
+        };
+        let items = self.split_searches(items, cwd.as_deref().unwrap_or(Path::new("")));
+
+        let Some(cwd) = cwd else {
+            return Ok(items);
+        };
+
+        Ok(items
+            .into_iter()
+            .map(|item| {
+                if item.is_absolute() {
+                    return item;
+                }
+
+                let resolved = cwd.join(&item);
+                self.opts.move_file_settings(&item, &resolved);
+                resolved
+            })
+            .collect())
+    }
+
+    fn split_searches(&mut self, items: Vec<PathBuf>, base: &Path) -> Vec<PathBuf> {
+        items
+            .into_iter()
+            .map(|item| match split_search(&item, base) {
+                Some((file, pattern)) => {
+                    self.opts.searches.insert(file.clone(), pattern);
+                    file
+                }
+                None => item,
+            })
+            .collect()
+    }
+
+    // Too many arguments stop gvi, unless it's asked to go on with the first ones. A range counts
+    // for as many arguments as it has members, which are worked out from its bounds, so that
+    // `{1..3000000000}` is turned down before any of them is built.
+    fn limit_arguments(&mut self, items: Vec<PathBuf>) -> Result<Vec<PathBuf>, AppError> {
+        let limit = self.opts.limits.max_args;
+        let count = count_arguments(&items);
+
+        if count > limit {
+            if !self.opts.truncate_args {
+                return Err(self.too_many_arguments(&items, count));
+            }
+            self.warnings
+                .push(Warning::TruncatedArguments(count, limit));
+        }
+
+        Ok(expand_range_arguments(items, limit, &mut self.warnings))
+    }
+
+    fn too_many_arguments(&self, items: &[PathBuf], count: usize) -> AppError {
+        let listed = match self.opts.limit_message_detail {
+            MessageDetail::Detailed => items.to_vec(),
+            _ => vec![],
+        };
+
+        AppError::TooManyArguments {
+            count,
+            limit: self.opts.limits.max_args,
+            listed,
+        }
+    }
+
+    // Whether to go on and open the files, after the limits and the confirmation.
+    fn ready_to_open(&mut self) -> Result<bool, AppError> {
+        self.check_limits()?;
+
+        if self.needs_confirmation()
+            && !self.confirm(&mut std::io::stdin().lock(), &mut std::io::stderr())
+        {
+            return Ok(false);
+        }
+
+        // A while may have passed since the expansion, at the confirmation most of all.
+        Ok(self.revalidate_files())
+    }
+
+    // What counts is how many files the arguments come to, not how many arguments there are.
+    // Opens nothing and exits if the files are too many or too large.
+    fn check_limits(&mut self) -> Result<(), AppError> {
+        let started = Instant::now();
+
+        if self.has_too_many_files() {
+            self.fail(self.too_many_files_message());
+        }
+
+        // check if total size of the files is small enough to be acceptable
+        if self.opts.until_size.is_none() && self.has_large_size_of_files()? {
+            self.fail(self.too_large_message());
+        }
+
+        self.phases.push(("size check", started.elapsed()));
+        Ok(())
+    }
+
+    fn has_too_many_files(&self) -> bool {
+        self.files.len() > self.opts.limits.max_files
+    }
+
+    fn too_many_files_message(&self) -> String {
+        let mut message = format!(
+            "Nothing has been opened, because the arguments come to {} files (at most {}).",
+            self.files.len(),
+            self.opts.limits.max_files
+        );
+
+        if self.opts.limit_message_detail == MessageDetail::Detailed {
+            for file in &self.files {
+                message.push_str(&format!("\n  {}", file.display()));
+            }
+        }
+
+        message
+    }
+
+    // Lists the files up to the one that went over the limit, since collect_files stops there.
+    fn too_large_message(&self) -> String {
+        let mut message = format!(
+            "Nothing has been opened, because the files add up to more than {} bytes.",
+            self.opts.limits.max_size
+        );
+
+        if self.opts.limit_message_detail == MessageDetail::Detailed {
+            for file in &self.files {
+                if let Ok(metadata) = self.fs.metadata(file)
+                    && metadata.is_file()
+                {
+                    message.push_str(&format!("\n  {}: {} bytes", file.display(), metadata.len()));
+                }
+            }
+        }
+
+        message
+    }
+
+    fn has_large_size_of_files(&self) -> Result<bool, AppError> {
+        // collect_files has seen every file already.
+        if let Some(size) = self.size {
+            return Ok(size > self.opts.limits.max_size);
+        }
+
+        let mut sum = 0;
+        let mut res = false;
+
+        for f in &self.files {
+            match self.fs.metadata(f) {
+                // Directories (see --include-dirs) are listed, not loaded.
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(metadata) => {
+                    let size = metadata.len();
+
+                    sum += size;
+
+                    if sum > self.opts.limits.max_size {
+                        res = true;
+                    }
+                }
+                Err(source) if self.opts.strict => {
+                    return Err(AppError::Io {
+                        path: f.clone(),
+                        source,
+                    });
+                }
+                Err(_) => {}
+            }
+        }
+
+        Ok(res)
+    }
+
+    // Looking for a running instance happens along the way, and is told apart for --profile.
+    fn open(&mut self) -> Result<(), AppError> {
+        let started = Instant::now();
+        let result = self.hand_over();
+        let detection = std::mem::take(&mut self.gvim.detection);
+
+        self.phases.push(("detection", detection));
+        self.phases
+            .push(("spawning", started.elapsed().saturating_sub(detection)));
+        result
+    }
+
+    fn hand_over(&mut self) -> Result<(), AppError> {
+        if let Some(opener) = &mut self.opener {
+            return opener.open(&self.files, &self.opts);
+        }
+
+        let plan = self.plan();
+        self.carry_out(plan)
+    }
+
+    // The servers the files go to and the instances there, before anything is opened.
+    fn plan(&mut self) -> OpenPlan {
+        let default = self.gvim.server_name.clone();
+        let groups = match self.config.routes.is_empty() || self.opts.open_session.is_some() {
+            true => vec![(default.clone(), self.files.clone())],
+            false => self.routed_files(&default),
+        };
+        let mut targets = vec![];
+
+        for (server, files) in groups {
+            self.gvim.server_name = server;
+            let (instance, explanation) = self.gvim.decide_instance(&files, &self.opts);
+            let files = files
+                .into_iter()
+                .map(|path| PlannedFile {
+                    line: self.opts.lines.get(&path).copied(),
+                    search: self.opts.searches.get(&path).cloned(),
+                    filetype: self.opts.filetypes.get(&path).cloned(),
+                    path,
+                })
+                .collect();
+
+            targets.push(PlannedTarget {
+                // The one the instance turned out to be registered under, if it was found.
+                server: self.gvim.server_name.clone(),
+                instance,
+                explanation,
+                files,
+            });
+        }
+
+        self.gvim.server_name = default;
+        OpenPlan { targets }
+    }
+
+    // --explain tells each target as planned, followed by the commands opening its files make
+    // (or would make, if it leaves it at that).
+    fn carry_out(&mut self, plan: OpenPlan) -> Result<(), AppError> {
+        let default = self.gvim.server_name.clone();
+
+        for target in plan.targets {
+            let files: Vec<PathBuf> = target.files.into_iter().map(|file| file.path).collect();
+
+            for line in target.explanation {
+                self.gvim.explain(line);
+            }
+            self.gvim.server_name = target.server;
+            self.gvim.open_in(&files, target.instance, &self.opts)?;
+        }
+
+        self.gvim.server_name = default;
+
+        Ok(())
+    }
+
+    // Group the files by the server they are routed to, in order of first appearance.
+    fn routed_files(&self, default: &str) -> Vec<(String, Vec<PathBuf>)> {
+        let mut groups: Vec<(String, Vec<PathBuf>)> = vec![];
+
+        for file in &self.files {
+            let server = self.config.route(file).unwrap_or(default);
+
+            match groups.iter_mut().find(|(name, _)| name == server) {
+                Some((_, files)) => files.push(file.clone()),
+                None => groups.push((server.to_string(), vec![file.clone()])),
+            }
+        }
+
+        groups
+    }
+
+    // Stops as soon as the files get too large in total, which has_large_size_of_files reports.
+    fn collect_files(&mut self, paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, AppError> {
+        let started = Instant::now();
+        let files = self.expand_paths(paths);
+        self.phases.push(("expansion", started.elapsed()));
+        files
+    }
+
+    fn expand_paths(&mut self, paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, AppError> {
+        let mut files = vec![];
+        let mut count: usize = 0;
+        let mut size: u64 = 0;
+
+        if self.opts.no_expand {
+            for (i, path) in paths.iter().enumerate() {
+                match self.fs.metadata(path) {
+                    Ok(metadata) if metadata.is_dir() => {
+                        return Err(AppError::DirectoryArgument(path.clone()));
+                    }
+                    Ok(metadata) => size += metadata.len(),
+                    Err(_) => {}
+                }
+
+                if self.opts.tab_per_arg {
+                    self.opts.sources.entry(path.clone()).or_insert(i);
+                }
+            }
+
+            self.size = Some(size);
+            return Ok(paths);
+        }
+
+        for (i, p) in paths.into_iter().enumerate() {
+            // --until-size cuts the files short only once they are all known.
+            let size_budget = match self.opts.until_size {
+                Some(_) => u64::MAX,
+                None => self.opts.limits.max_size,
+            };
+            let expansion = expand_dir(
+                p,
+                self.opts.limits.max_total - count,
+                size_budget - size,
+                &self.opts,
+                &*self.fs,
+            )?;
+
+            if let Some(dir) = expansion.limit_hit {
+                return Err(AppError::TooManyEntries {
+                    dir,
+                    collected: files.len() + expansion.files.len(),
+                    limit: self.opts.limits.max_total,
+                });
+            }
+
+            for file in &expansion.long_names {
+                self.warnings.push(Warning::LongName(
+                    file.clone(),
+                    self.opts.limits.max_name_length.unwrap_or_default(),
+                ));
+            }
+
+            for (file, file_size) in &expansion.oversized {
+                self.warnings.push(Warning::Oversized {
+                    path: file.clone(),
+                    size: *file_size,
+                    limit: self.opts.limits.max_size_per_file.unwrap_or_default(),
+                    opened: expansion.files.contains(file),
+                });
+            }
+
+            if self.opts.tab_per_arg {
+                for file in &expansion.files {
+                    self.opts.sources.entry(file.clone()).or_insert(i);
+                }
+            }
+
+            self.counts.dirs += expansion.dirs;
+            self.counts.files += expansion.files.len();
+            self.counts.skipped += expansion.skipped;
+
+            count += expansion.count;
+            size += expansion.size;
+            files.extend(expansion.files);
+
+            if expansion.size_limit_hit {
+                break;
+            }
+        }
+
+        self.size = Some(size);
+
+        Ok(files)
+    }
+
+    // The same file may come from several arguments, e.g. `gvi src src/main.rs`. Keep the first.
+    fn dedupe_files(&mut self) {
+        match self.opts.dedupe_by {
+            DedupeKey::Path => {
+                let mut seen = std::collections::HashSet::new();
+                self.files.retain(|file| seen.insert(file.clone()));
+            }
+            #[cfg(unix)]
+            DedupeKey::Inode => {
+                use std::os::unix::fs::MetadataExt;
+
+                let mut seen = std::collections::HashSet::new();
+                let fs = &self.fs;
+                self.files.retain(|file| match fs.metadata(file) {
+                    // Directories (see --include-dirs) have inodes as well.
+                    Ok(metadata) => seen.insert((metadata.dev(), metadata.ino())),
+                    Err(_) => true,
+                });
+            }
+            DedupeKey::Content => {
+                // Only files of the same size can be identical, so only those are read.
+                let fs = &self.fs;
+                let sizes: Vec<Option<u64>> = self
+                    .files
+                    .iter()
+                    .map(|file| {
+                        fs.metadata(file)
+                            .ok()
+                            .filter(|m| m.is_file())
+                            .map(|m| m.len())
+                    })
+                    .collect();
+                let mut counts = std::collections::HashMap::new();
+                for &size in sizes.iter().flatten() {
+                    *counts.entry(size).or_insert(0) += 1;
+                }
+
+                let mut seen = std::collections::HashSet::new();
+                let mut sizes = sizes.into_iter();
+                self.files.retain(|file| match sizes.next().flatten() {
+                    Some(size) if counts[&size] > 1 => match content_hash(file) {
+                        Ok(hash) => seen.insert((size, hash)),
+                        Err(_) => true,
+                    },
+                    _ => true,
+                });
+            }
+        }
+    }
+
+    // With --until-size, keep the files up to the first that doesn't fit any more.
+    fn take_until_size(&mut self) {
+        let Some(budget) = self.opts.until_size else {
+            return;
+        };
+
+        let mut size = 0;
+        for (i, file) in self.files.iter().enumerate() {
+            // Directories (see --include-dirs) are listed, not loaded.
+            let len = match self.fs.metadata(file) {
+                Ok(metadata) if metadata.is_file() => metadata.len(),
+                _ => 0,
+            };
+
+            if size + len > budget {
+                let skipped = self.files.split_off(i);
+                self.warnings
+                    .push(Warning::OverSizeBudget { skipped, budget });
+                break;
+            }
+            size += len;
+        }
+
+        self.size = Some(size);
+    }
+
+    // Leave out the files that have gone since they were collected or turned into directories,
+    // rather than have gvim open a new buffer or netrw for them; false if none is left.
+    fn revalidate_files(&mut self) -> bool {
+        if self.files.is_empty() {
+            return true;
+        }
+
+        let fs = &self.fs;
+        let include_dirs = self.opts.include_dirs;
+        let mut gone = vec![];
+        self.files.retain(|file| match fs.metadata(file) {
+            Ok(metadata) if metadata.is_file() || include_dirs => true,
+            _ => {
+                gone.push(Warning::GoneBeforeOpening(file.clone()));
+                false
+            }
+        });
+        self.warnings.extend(gone);
+
+        !self.files.is_empty()
+    }
+
+    // With --decompress, compressed files are opened as read-only copies decompressed into `dir`,
+    // which are left there for gvim to read. Files that can't be decompressed stay as they are.
+    // A copy is written up to the size, --until-size or --max-size, left to the files before it,
+    // so that a small file decompressing into gigabytes doesn't fill the temporary directory.
+    // With --until-size one cut short is left out along with the files after it, like
+    // take_until_size does; otherwise it's there for check_limits to turn the files down.
+    fn decompress_files(&mut self, dir: &Path) {
+        if !self.opts.decompress {
+            return;
+        }
+
+        remove_stale_copies(dir);
+
+        let budget = self.opts.until_size.unwrap_or(self.opts.limits.max_size);
+        let mut size: u64 = 0;
+        let pid = std::process::id();
+
+        for i in 0..self.files.len() {
+            let file = self.files[i].clone();
+            let left = budget.saturating_sub(size);
+            let Some(compression) = Compression::of(&file) else {
+                // Directories (see --include-dirs) are listed, not loaded.
+                size += match self.fs.metadata(&file) {
+                    Ok(metadata) if metadata.is_file() => metadata.len(),
+                    _ => 0,
+                };
+                continue;
+            };
+
+            // Without the extension of the compression, so that gvim still sees the file type.
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            let name = name.strip_suffix(compression.extension()).unwrap_or(&name);
+            let name = |attempt| match attempt {
+                0 => format!("gvi-{}-{}-{}", pid, i, name),
+                _ => format!("gvi-{}-{}.{}-{}", pid, i, attempt, name),
+            };
+            // A byte over what's left tells a copy cut short from one that just fits.
+            let copy = match decompress(&file, compression, dir, name, left.saturating_add(1)) {
+                Ok(copy) => copy,
+                Err(_) => {
+                    self.warnings.push(Warning::NotDecompressed(file));
+                    continue;
+                }
+            };
+            let len = std::fs::metadata(&copy).map_or(0, |metadata| metadata.len());
+
+            if len > left && self.opts.until_size.is_some() {
+                remove_copy(&copy);
+                let skipped = self.files.split_off(i);
+                self.warnings
+                    .push(Warning::OverSizeBudget { skipped, budget });
+                break;
+            }
+
+            if !self.opts.quiet {
+                report(
+                    Severity::Note,
+                    &format!(
+                        "{} is opened from a read-only copy, decompressed: {}",
+                        file.display(),
+                        copy.display()
+                    ),
+                );
+            }
+            self.opts.move_file_settings(&file, &copy);
+            self.copies.push(copy.clone());
+            self.files[i] = copy;
+            size += len;
+
+            if len > left {
+                break;
+            }
+        }
+
+        // The copies take the place of the files, sizes included.
+        if !self.copies.is_empty() {
+            self.size = Some(size);
+        }
+    }
+
+    fn remove_copies(&mut self) {
+        for copy in self.copies.drain(..) {
+            remove_copy(&copy);
+        }
+    }
+
+    // collect_files keeps the argument order, with the contents of a directory in its place.
+    fn order_files(&mut self) {
+        if self.opts.preserve_arg_order {
+            return;
+        }
+
+        if let Some(key) = self.opts.sort_by {
+            sort_files(&mut self.files, key);
+        }
+
+        if self.opts.group_by_dir {
+            group_by_dir(&mut self.files);
+        }
+
+        if self.opts.reverse {
+            self.files.reverse();
+        }
+    }
+
+    // Keep the best --fuzzy-n matches, best first. With just one wanted and several files
+    // matching equally well, ask which one when we can; false if the answer is none of them.
+    fn pick_fuzzy_matches(
+        &mut self,
+        query: &str,
+        input: &mut impl std::io::BufRead,
+        output: &mut impl std::io::Write,
+    ) -> bool {
+        use std::io::IsTerminal;
+
+        let ranked = rank_fuzzy_matches(query, &self.files);
+        let wanted = self.opts.fuzzy_n.unwrap_or(1);
+
+        if wanted == 1 && ranked.len() > 1 && ranked[0].0 == ranked[1].0 {
+            let tied: Vec<PathBuf> = ranked
+                .iter()
+                .take_while(|(rank, _)| *rank == ranked[0].0)
+                .map(|(_, file)| file.clone())
+                .collect();
+
+            if !self.opts.yes && std::io::stdin().is_terminal() {
+                match choose(&tied, input, output) {
+                    Some(file) => self.files = vec![file],
+                    None => return false,
+                }
+                return true;
+            }
+        }
+
+        self.files = ranked
+            .into_iter()
+            .take(wanted)
+            .map(|(_, file)| file)
+            .collect();
+        true
+    }
+
+    // Without a terminal to answer on, --select takes every file.
+    fn wants_selection(&self) -> bool {
+        use std::io::IsTerminal;
+
+        self.opts.select && !self.opts.all && std::io::stdin().is_terminal()
+    }
+
+    fn needs_confirmation(&self) -> bool {
+        use std::io::IsTerminal;
+
+        match self.opts.confirm {
+            Some(threshold) => {
+                self.files.len() > threshold && !self.opts.yes && std::io::stdin().is_terminal()
+            }
+            None => false,
+        }
+    }
+
+    fn confirm(&self, input: &mut impl std::io::BufRead, output: &mut impl std::io::Write) -> bool {
+        let _ = write!(
+            output,
+            "about to open {} files, continue? [y/N] ",
+            self.files.len()
+        );
+        let _ = output.flush();
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer).is_err() {
+            return false;
+        }
+
+        matches!(answer.trim(), "y" | "Y" | "yes")
+    }
+
+    fn run(&mut self) {
+        let result = self.execute();
+        self.report_warnings();
+
+        if self.opts.profile {
+            self.print_profile(&mut std::io::stderr());
+        }
+
+        match result {
+            Err(err @ AppError::Interrupted(_)) => {
+                report(Severity::Error, &err.to_string());
+                // As if we had been killed by SIGINT, the way shells report it.
+                std::process::exit(130);
+            }
+            Err(err) => {
+                report(Severity::Error, &err.to_string());
+                std::process::exit(1);
+            }
+            Ok(()) => {}
+        }
+
+        std::process::exit(0);
+    }
+
+    // The config file only holds defaults: the same options on the command line override it.
+    fn load_config(&mut self, path: PathBuf, required: bool) -> Result<(), AppError> {
+        let started = Instant::now();
+        let result = self.read_config(path, required);
+        self.phases.push(("config", started.elapsed()));
+        result
+    }
+
+    fn read_config(&mut self, path: PathBuf, required: bool) -> Result<(), AppError> {
+        if required && !path.is_file() {
+            return Err(AppError::MissingConfig(path));
+        }
+
+        self.config = Config::load(&path)
+            .map_err(|message| AppError::InvalidConfig(path.clone(), message))?;
+
+        let mut args = self.config.args();
+        for name in self.config.profiles.keys() {
+            args.extend(self.config.profile_args(name).unwrap_or_default());
+        }
+        let (_, unknown) = Options::parse(&args)?;
+
+        if let Some(arg) = unknown.first() {
+            let key = arg
+                .trim_start_matches("--")
+                .split('=')
+                .next()
+                .unwrap_or_default();
+            let message = format!("unknown setting `{}`", key);
+            return Err(AppError::InvalidConfig(path, message));
+        }
+
+        Ok(())
+    }
+
+    // Print the settings in effect as TOML, noting where each value came from with --verbose.
+    fn dump_config(
+        &self,
+        env: impl Fn(&str) -> Option<String>,
+        output: &mut impl std::io::Write,
+    ) -> Result<(), AppError> {
+        let (flags, _) = Options::parse(&self.args[1..])?;
+        let io_error = |source| AppError::Io {
+            path: PathBuf::from("-"),
+            source,
+        };
+
+        let profile = self
+            .opts
+            .profile_name
+            .as_ref()
+            .and_then(|name| self.config.profiles.get(name));
+
+        for (name, value) in self.opts.settings() {
+            let env_value = ENV_SETTINGS
+                .iter()
+                .find(|(setting, _)| *setting == name)
+                .and_then(|(_, key)| env(key))
+                .filter(|value| !value.is_empty());
+
+            let (value, source) = if flags.given.iter().any(|given| given == name) {
+                (value, Source::Flag)
+            } else if self.config.options.iter().any(|(key, _)| key == name)
+                || profile.is_some_and(|options| options.iter().any(|(key, _)| key == name))
+            {
+                (value, Source::Config)
+            } else if let Some(env_value) = env_value {
+                (Some(config::quote(&env_value)), Source::Env)
+            } else {
+                (value, Source::Default)
+            };
+
+            let line = match value {
+                Some(value) => format!("{} = {}", name, value),
+                // TOML has no null, so only mention unset settings when asked to.
+                None if self.opts.verbose => format!("# {} is unset", name),
+                None => continue,
+            };
+
+            if self.opts.verbose {
+                writeln!(output, "{} # {}", line, source.name()).map_err(io_error)?;
+            } else {
+                writeln!(output, "{}", line).map_err(io_error)?;
+            }
+        }
+
+        if !self.config.routes.is_empty() {
+            let mut routes: Vec<_> = self.config.routes.iter().collect();
+            routes.sort();
+
+            writeln!(output, "\n[routes]").map_err(io_error)?;
+            for (ext, server) in routes {
+                writeln!(output, "{} = {}", ext, config::quote(server)).map_err(io_error)?;
+            }
+        }
+
+        if !self.config.presets.is_empty() {
+            let mut presets: Vec<_> = self.config.presets.iter().collect();
+            presets.sort_by_key(|(name, _)| name.as_str());
+
+            writeln!(output, "\n[presets]").map_err(io_error)?;
+            for (name, layout) in presets {
+                writeln!(output, "{} = {}", name, config::quote(&layout.to_string()))
+                    .map_err(io_error)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list_opened(
+        &mut self,
+        output: &mut impl std::io::Write,
+        status: &mut impl std::io::Write,
+    ) -> Result<(), AppError> {
+        let files = self.gvim.opened_files()?;
+        let io_error = |source| AppError::Io {
+            path: PathBuf::from("-"),
+            source,
+        };
+
+        if files.is_empty() {
+            writeln!(status, "Nothing is open in {}.", self.gvim.server_name).map_err(io_error)?;
+        }
+
+        for file in files {
+            writeln!(output, "{}", file.display()).map_err(io_error)?;
+        }
+
+        Ok(())
+    }
+
+    // Absolute, so that the list still works from another directory.
+    fn save_list(&self, list: &Path) -> Result<(), AppError> {
+        let mut content = String::new();
+
+        for file in &self.files {
+            let file = std::path::absolute(file).unwrap_or_else(|_| file.clone());
+            content.push_str(&format!("{}\n", file.display()));
+        }
+
+        std::fs::write(list, content).map_err(|source| AppError::Io {
+            path: list.to_path_buf(),
+            source,
+        })
+    }
+
+    fn print_count(&self, output: &mut impl std::io::Write) -> Result<(), AppError> {
+        let size = self.size.unwrap_or_default();
+        // collect_files doesn't go on once the files are too large.
+        let more = if size > self.opts.limits.max_size {
+            " or more"
+        } else {
+            ""
+        };
+
+        writeln!(
+            output,
+            "{} files{}, {} bytes{}",
+            self.files.len(),
+            more,
+            size,
+            more
+        )
+        .map_err(|source| AppError::Io {
+            path: PathBuf::from("-"),
+            source,
+        })
+    }
+
+    fn print_dir_count(&self, output: &mut impl std::io::Write) -> Result<(), AppError> {
+        writeln!(
+            output,
+            "{} directories, {} files, {} skipped",
+            self.counts.dirs, self.counts.files, self.counts.skipped
+        )
+        .map_err(|source| AppError::Io {
+            path: PathBuf::from("-"),
+            source,
+        })
+    }
+
+    fn wants_windows_gvim(&self) -> bool {
+        let osrelease = std::fs::read_to_string("/proc/sys/kernel/osrelease").ok();
+        let under_wsl = is_wsl(|key| std::env::var(key).ok(), osrelease.as_deref());
+
+        wants_windows_gvim(&self.opts, under_wsl, || which::which("gvim").is_ok())
+    }
+
+    // gvim can read stdin itself, but only a fresh instance can, and only in place of any file.
+    // A temporary file works everywhere; it is left behind for gvim to keep using.
+    fn read_stdin_to_file(
+        &mut self,
+        input: &mut impl Read,
+        dir: &Path,
+    ) -> Result<PathBuf, AppError> {
+        use std::io::Write;
+
+        let mut content = vec![];
+
+        input
+            .read_to_end(&mut content)
+            .map_err(|source| AppError::Io {
+                path: PathBuf::from("-"),
+                source,
+            })?;
+
+        let pid = std::process::id();
+        let (path, mut file) = create_temp_file(dir, |attempt| match attempt {
+            0 => format!("gvi-stdin-{}", pid),
+            _ => format!("gvi-stdin-{}-{}", pid, attempt),
+        })
+        .map_err(|source| AppError::Io {
+            path: dir.join(format!("gvi-stdin-{}", pid)),
+            source,
+        })?;
+        file.write_all(&content).map_err(|source| AppError::Io {
+            path: path.clone(),
+            source,
+        })?;
+
+        if let Some(filetype) = &self.opts.filetype {
+            self.opts.filetypes.insert(path.clone(), filetype.clone());
+        }
+
+        Ok(path)
+    }
+
+    // The settings of the config file, then those of the --profile-name profile, then the
+    // command line, so that each wins over the ones before.
+    fn config_args(&self, args: &[String]) -> Result<Vec<String>, AppError> {
+        let (given, _) = Options::parse(&[self.config.args(), args.to_vec()].concat())?;
+
+        let profile = match &given.profile_name {
+            Some(name) => self
+                .config
+                .profile_args(name)
+                .ok_or_else(|| AppError::UnknownProfile(name.clone()))?,
+            None => vec![],
+        };
+
+        Ok([self.config.args(), profile, args.to_vec()].concat())
+    }
+
+    // --layout, then --preset, then $GVI_LAYOUT, whose value is warned about and passed over if
+    // it isn't a layout. A tab page per file is what gvi does anyway.
+    fn resolve_layout(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), AppError> {
+        if self.opts.layout.is_none()
+            && let Some(name) = &self.opts.preset
+        {
+            let layout = self.config.presets.get(name);
+            self.opts.layout = Some(*layout.ok_or_else(|| AppError::UnknownPreset(name.clone()))?);
+        }
+
+        if self.opts.layout.is_none()
+            && let Some(value) = env("GVI_LAYOUT").filter(|value| !value.is_empty())
+        {
+            match Layout::parse(&value) {
+                Ok(layout) => self.opts.layout = Some(layout),
+                Err(_) => self.warnings.push(Warning::InvalidLayout(value)),
+            }
+        }
+
+        self.opts.layout = self.opts.layout.filter(|layout| layout.per_tab != Some(1));
+
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<(), AppError> {
+        if let Some((path, required)) = Config::location(|key| std::env::var(key).ok()) {
+            self.load_config(path, required)?;
+        }
+
+        // Subcommands come first; anywhere else "close" is just a file by that name.
+        let close = self.args.get(1).is_some_and(|arg| arg == "close");
+        let skip = if close { 2 } else { 1 };
+
+        // split the necessary part of the args.
+        let args = self.config_args(&self.args[skip..])?;
+        let (opts, items) = Options::parse(&args)?;
+        self.opts = opts;
+
+        {
+            use std::io::IsTerminal;
+
+            let color = self.opts.color.enabled(
+                |key| std::env::var(key).ok(),
+                std::io::stderr().is_terminal(),
+            );
+            COLOR.store(color, Ordering::SeqCst);
+        }
+
+        if self.opts.dump_config {
+            return self.dump_config(|key| std::env::var(key).ok(), &mut std::io::stdout());
+        }
+
+        if let Some(session) = &self.opts.open_session {
+            std::fs::File::open(session).map_err(|source| AppError::Io {
+                path: session.clone(),
+                source,
+            })?;
+        }
+
+        let mut items: Vec<PathBuf> = items.into_iter().map(PathBuf::from).collect();
+
+        if self.opts.null {
+            let mut input = vec![];
+            std::io::stdin()
+                .read_to_end(&mut input)
+                .map_err(|source| AppError::Io {
+                    path: PathBuf::from("-"),
+                    source,
+                })?;
+            items.extend(split_null_separated(&input));
+        }
+
+        if let Some(i) = items.iter().position(|item| item == Path::new("-")) {
+            // Whatever there was on stdin went to the paths already.
+            if self.opts.null {
+                return Err(AppError::InvalidOptionValue(
+                    "-0".to_string(),
+                    "-".to_string(),
+                ));
+            }
+
+            items[i] = self.read_stdin_to_file(&mut std::io::stdin(), &std::env::temp_dir())?;
+        }
+
+        if let Some(list) = &self.opts.from_quickfix {
+            let content = std::fs::read_to_string(list).map_err(|source| AppError::Io {
+                path: list.clone(),
+                source,
+            })?;
+
+            for (path, line) in parse_quickfix(&content) {
+                if let Some(line) = line {
+                    self.opts.lines.insert(path.clone(), line);
+                }
+                items.push(path);
+            }
+        }
+
+        if let Some(list) = &self.opts.manifest {
+            let content = std::fs::read_to_string(list).map_err(|source| AppError::Io {
+                path: list.clone(),
+                source,
+            })?;
+            let entries = manifest::parse(&content)
+                .map_err(|message| AppError::InvalidManifest(list.clone(), message))?;
+
+            for (path, line) in entries {
+                if let Some(line) = line {
+                    self.opts.lines.insert(path.clone(), line);
+                }
+                items.push(path);
+            }
+        }
+
+        self.resolve_layout(|key| std::env::var(key).ok())?;
+
+        if let Some(reference) = &self.opts.since_commit {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let root = git_root(&cwd).unwrap_or(cwd);
+
+            items.extend(changed_since(&mut SystemRunner, reference, &root)?);
+        }
+
+        let items = expand_list_files(items)?;
+
+        self.gvim.startup_timeout = Gvim::resolve_startup_timeout(
+            &self.opts,
+            |key| std::env::var(key).ok(),
+            &mut self.gvim.warnings,
+        );
+
+        match self.opts.opener {
+            OpenerKind::Gvim if self.wants_windows_gvim() => {
+                self.gvim.editor = which::which(Gvim::WINDOWS_EDITOR)
+                    .map_err(|_| AppError::OpenerNotFound(Gvim::WINDOWS_EDITOR.to_string()))?;
+                self.gvim.translate = Some(windows_path);
+                self.gvim.detector = wsl_detector(self.gvim.editor.clone());
+                self.gvim.cache = CapabilityCache::default_location(|key| std::env::var(key).ok());
+                self.gvim.server_name =
+                    Gvim::resolve_server_name(&self.opts, |key| std::env::var(key).ok());
+            }
+            OpenerKind::Gvim => {
+                self.gvim.editor = Gvim::resolve_editor(
+                    &self.opts,
+                    |key| std::env::var(key).ok(),
+                    |name| which::which(name).ok(),
+                )?;
+
+                if self.opts.verbose {
+                    eprintln!("Using {}", self.gvim.editor.display());
+                }
+                self.gvim.detector = Box::new(CombinedDetector::new(self.gvim.editor.clone()));
+                self.gvim.cache = CapabilityCache::default_location(|key| std::env::var(key).ok());
+                self.gvim.server_name =
+                    Gvim::resolve_server_name(&self.opts, |key| std::env::var(key).ok());
+            }
+            OpenerKind::Code => {
+                let name = self.opts.opener.name();
+                let editor =
+                    which::which(name).map_err(|_| AppError::OpenerNotFound(name.to_string()))?;
+
+                self.opener = Some(Box::new(CodeOpener {
+                    editor,
+                    runner: Box::new(SystemRunner),
+                }));
+            }
+        }
+
+        if self.opts.list_opened {
+            return self.list_opened(&mut std::io::stdout(), &mut std::io::stderr());
+        }
+
+        if let Some((old, new)) = &self.opts.rename_server {
+            return self.gvim.rename_server(old, new);
+        }
+
+        if self.opts.keep_alive {
+            return match items.as_slice() {
+                [] => self.gvim.keep_alive(),
+                _ => Err(AppError::InvalidUsage(
+                    "gvi --keep-alive [--server-name <name>]",
+                )),
+            };
+        }
+
+        if close {
+            return match items.as_slice() {
+                [name] => self.gvim.close(&name.to_string_lossy(), self.opts.force),
+                _ => Err(AppError::InvalidUsage("gvi close [--force] <server name>")),
+            };
+        }
+
+        let items = self.resolve_against_server(items)?;
+
+        // Which server there is doesn't depend on the files, so it's looked for while they are
+        // collected, and plan waits for the answer. --fast may not look at all.
+        if self.opener.is_none() && !self.opts.fast && self.opts.open_session.is_none() {
+            self.gvim.detect_in_background();
+        }
+
+        let items = self.limit_arguments(items)?;
+
+        // Otherwise the arguments we can't open are just left out.
+        if self.opts.strict {
+            validate_arguments(&items)?;
+        }
+
+        // expand all the items (including internal ones) if each of them is a directory.
+        let paths = existing_arguments(items, &mut self.warnings);
+
+        self.files = self.collect_files(paths)?;
+        if self.opts.count_dirs || self.opts.verbose {
+            self.print_dir_count(&mut std::io::stderr())?;
+        }
+        self.dedupe_files();
+        self.order_files();
+
+        if let Some(query) = self.opts.fuzzy.clone() {
+            if !self.pick_fuzzy_matches(
+                &query,
+                &mut std::io::stdin().lock(),
+                &mut std::io::stderr(),
+            ) {
+                return Ok(());
+            }
+
+            if self.files.is_empty() {
+                return Err(AppError::NoMatch(query));
+            }
+        }
+
+        if self.wants_selection() {
+            self.files = select(
+                &self.files,
+                &mut std::io::stdin().lock(),
+                &mut std::io::stderr(),
+            );
+
+            if self.files.is_empty() {
+                return Ok(());
+            }
+        }
+
+        self.take_until_size();
+
+        if let Some(pattern) = &self.opts.search {
+            for file in &self.files {
+                self.opts
+                    .searches
+                    .entry(file.clone())
+                    .or_insert_with(|| pattern.clone());
+            }
+        }
+
+        if let Some(list) = &self.opts.save_list {
+            self.save_list(list)?;
+        }
+
+        if self.opts.count_only {
+            return self.print_count(&mut std::io::stdout());
+        }
+
+        if self.opts.diff && self.files.len() != 2 {
+            return Err(AppError::InvalidUsage("gvi --diff <file> <file>"));
+        }
+
+        // What gvim is going to load is the decompressed copies, so their sizes are what counts.
+        self.decompress_files(&std::env::temp_dir());
+
+        // Copies nothing is going to open are of no use to anyone.
+        match self.ready_to_open() {
+            Ok(true) => {}
+            result => {
+                self.remove_copies();
+                return result.map(|_| ());
+            }
+        }
+
+        // Not any earlier: until now Ctrl-C may as well kill us, even at the confirmation.
+        install_interrupt_handler();
+
+        if self.opts.explain || self.opts.explain_and_open {
+            self.gvim.explanation = Some(vec![]);
+            self.gvim.dry = !self.opts.explain_and_open;
+        }
+
+        let result = self.open();
+
+        // Up to where it went wrong, if it did.
+        for line in self.gvim.explanation.take().unwrap_or_default() {
+            println!("{}", line);
+        }
+
+        if self.gvim.dry || (result.is_err() && self.gvim.opened == 0) {
+            self.remove_copies();
+        }
+        result?;
+
+        if self.gvim.dry {
+            return Ok(());
+        }
+
+        if self.opts.watch {
+            // The end of the run is a Ctrl-C away, which is too late for these.
+            self.report_warnings();
+
+            // Check the files every Watcher::INTERVAL and have gvim reload the changed ones,
+            // until interrupted.
+            let mut watcher = Watcher::new(&self.files, modified_time);
+
+            while !self.gvim.interrupted.load(Ordering::SeqCst) {
+                std::thread::sleep(Watcher::INTERVAL);
+                self.gvim.reload_if_changed(&mut watcher, modified_time)?;
+            }
+
+            return Err(AppError::Interrupted(self.gvim.opened));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Expansion {
+    files: Vec<PathBuf>,
+    // Number of paths looked at (files as well as directories).
+    count: usize,
+    // The expansion stopped early because more than the allowed number of paths were seen, in
+    // this directory.
+    limit_hit: Option<PathBuf>,
+    // Total size of the files.
+    size: u64,
+    // The expansion stopped early because the files got larger than allowed.
+    size_limit_hit: bool,
+    // Files over --max-size-per-file, with their sizes. Left out, unless given as the argument
+    // along with --keep-large-args.
+    oversized: Vec<(PathBuf, u64)>,
+    // Files left out for names longer than --max-name-length.
+    long_names: Vec<PathBuf>,
+    // Directories whose entries were read.
+    dirs: usize,
+    // Entries left out by --ext, --fuzzy and the ignore rules.
+    skipped: usize,
+}
+
+// Added up over the expansions of all the arguments, for --count-dirs.
+#[derive(Debug, Default, PartialEq)]
+struct ExpansionCounts {
+    dirs: usize,
+    files: usize,
+    skipped: usize,
+}
+
+// Collect the files under the given path (or the path itself, if it's a file), looking at no
+// more than `budget` paths and `size_budget` bytes. Directories are walked depth first (or breadth
+// first, see --order) with their entries sorted by name. Every path is stat'ed once.
+fn expand_dir(
+    maybe_dir: PathBuf,
+    budget: usize,
+    size_budget: u64,
+    opts: &Options,
+    fs: &dyn FileSystem,
+) -> Result<Expansion, AppError> {
+    let mut expansion = Expansion::default();
+    // Paths along with how deep below the argument they are.
+    let mut pending = std::collections::VecDeque::from([(maybe_dir, 0)]);
+    // Those of the ignore files found so far, outer directories first.
+    let mut ignores: Vec<IgnoreRules> = vec![];
+    // With --latest: the newest file so far, its modification time and size.
+    let mut newest: Option<(PathBuf, Option<SystemTime>, u64)> = None;
+
+    let next = |pending: &mut std::collections::VecDeque<_>| match opts.order {
+        Traversal::DepthFirst => pending.pop_back(),
+        Traversal::BreadthFirst => pending.pop_front(),
+    };
+
+    while let Some((path, depth)) = next(&mut pending) {
+        expansion.count += 1;
+
+        if expansion.count > budget {
+            expansion.limit_hit = Some(match depth {
+                0 => path,
+                _ => parent_dir(&path),
+            });
+            break;
+        }
+
+        // metadata will traverse symbolic link.
+        let metadata = match fs.metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(source) if opts.strict => return Err(AppError::Io { path, source }),
+            Err(_) => continue,
+        };
+
+        if metadata.is_file() {
+            // Files given as arguments are always taken.
+            if depth > 0 && !(opts.wants_extension(&path) && opts.could_match(&path)) {
+                expansion.skipped += 1;
+                continue;
+            }
+
+            if let Some(cap) = opts.limits.max_name_length
+                && depth > 0
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().chars().count() > cap)
+            {
+                expansion.long_names.push(path);
+                continue;
+            }
+
+            if let Some(cap) = opts.limits.max_size_per_file
+                && metadata.len() > cap
+            {
+                expansion.oversized.push((path.clone(), metadata.len()));
+
+                if depth > 0 || !opts.keep_large_args {
+                    continue;
+                }
+            }
+
+            if opts.latest && depth > 0 {
+                let mtime = metadata.modified().ok();
+
+                // On a tie the first one by name wins.
+                if newest.as_ref().is_none_or(|(_, newest, _)| mtime > *newest) {
+                    newest = Some((path, mtime, metadata.len()));
+                }
+
+                continue;
+            }
+
+            expansion.size += metadata.len();
+            expansion.files.push(path);
+
+            if expansion.size > size_budget {
+                expansion.size_limit_hit = true;
+                break;
+            }
+
+            continue;
+        }
+
+        if metadata.is_dir() {
+            // Only the files directly in the directory given as an argument.
+            if opts.no_recurse && depth > 0 {
+                continue;
+            }
+
+            if opts
+                .limits
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth)
+            {
+                continue;
+            }
+
+            // gvim shows a directory with netrw, in a tab of its own.
+            if opts.include_dirs && depth == 0 {
+                expansion.files.push(path.clone());
+            }
+
+            expansion.dirs += 1;
+        }
+
+        ignores.extend(IgnoreRules::load(&path));
+
+        let entries = read_entries(&path, opts)?;
+        let listed = entries.len();
+        let entries: Vec<_> = entries
+            .into_iter()
+            .filter(|ent| {
+                // Doesn't follow symbolic links, but doesn't need a stat (on most systems) either.
+                let is_dir = ent.file_type().is_ok_and(|file_type| file_type.is_dir());
+                let by_default =
+                    is_dir && !opts.no_default_ignores && ignore::is_default_dir(&ent.path());
+                !by_default && !ignore::is_ignored(&ignores, &ent.path(), is_dir)
+            })
+            .collect();
+        expansion.skipped += listed - entries.len();
+
+        let mut children: Vec<(PathBuf, usize)> = entries
+            .into_iter()
+            .take(opts.limits.max_files)
+            .map(|ent| (ent.path(), depth + 1))
+            .collect();
+
+        // Depth first pops from the back, so this keeps the entries in order.
+        if opts.order == Traversal::DepthFirst {
+            children.reverse();
+        }
+        pending.extend(children);
+    }
+
+    if let Some((path, _, size)) = newest {
+        expansion.size += size;
+        expansion.size_limit_hit = expansion.size > size_budget;
+        expansion.files.push(path);
+    }
+
+    Ok(expansion)
+}
+
+// What --decompress knows to decompress, told by the first bytes of the file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    fn of(path: &Path) -> Option<Compression> {
+        let mut head = [0; 6];
+        let read = std::fs::File::open(path).ok()?.read(&mut head).ok()?;
+
+        match &head[..read] {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [b'B', b'Z', b'h', ..] => Some(Compression::Bzip2),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    // Each of them takes -dc to write what it decompresses to stdout.
+    fn program(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Bzip2 => ".bz2",
+            Compression::Xz => ".xz",
+        }
+    }
+}
+
+// Into a read-only file named by create_temp_file, so that gvim doesn't let the copy pass for the
+// original. No more than `limit` bytes are written. A copy that couldn't be finished is removed.
+fn decompress(
+    path: &Path,
+    compression: Compression,
+    dir: &Path,
+    name: impl Fn(usize) -> String,
+    limit: u64,
+) -> std::io::Result<PathBuf> {
+    let (copy, file) = create_temp_file(dir, name)?;
+
+    let result = write_decompressed(path, compression, file, limit).and_then(|()| {
+        let mut permissions = std::fs::metadata(&copy)?.permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&copy, permissions)
+    });
+
+    match result {
+        Ok(()) => Ok(copy),
+        Err(e) => {
+            remove_copy(&copy);
+            Err(e)
+        }
+    }
+}
+
+fn write_decompressed(
+    path: &Path,
+    compression: Compression,
+    mut file: std::fs::File,
+    limit: u64,
+) -> std::io::Result<()> {
+    use std::io::Read;
+
+    let mut child = Command::new(compression.program())
+        .arg("-dc")
+        .arg(path)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    let mut output = child.stdout.take().expect("stdout is piped");
+
+    let written = std::io::copy(&mut (&mut output).take(limit), &mut file)?;
+    drop(output);
+
+    // The rest isn't wanted, however much there is of it.
+    if written == limit {
+        let _ = child.kill();
+        let _ = child.wait();
+        return Ok(());
+    }
+
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(std::io::Error::other(format!(
+            "{} -dc failed",
+            compression.program()
+        ))),
+    }
+}
+
+// gvim has long read a copy by the time it's this old; we don't wait for it to, so the copies of
+// earlier runs are removed by the next one.
+const STALE_COPY_AGE: Duration = Duration::from_secs(60 * 60);
+
+fn remove_stale_copies(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_copy = is_decompressed_copy(&entry.file_name().to_string_lossy());
+        let stale = modified_time(&path)
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > STALE_COPY_AGE);
+
+        if is_copy && stale && path.is_file() {
+            remove_copy(&path);
+        }
+    }
+}
+
+fn remove_copy(path: &Path) {
+    // Windows won't remove a read-only file.
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let mut permissions = metadata.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        let _ = std::fs::set_permissions(path, permissions);
+    }
+    let _ = std::fs::remove_file(path);
+}
+
+// "gvi-<pid>-<index>-<name>", or "gvi-<pid>-<index>.<attempt>-<name>", as decompress_files names
+// them.
+fn is_decompressed_copy(name: &str) -> bool {
+    let mut parts = name.splitn(4, '-');
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    parts.next() == Some("gvi")
+        && parts.next().is_some_and(digits)
+        && parts.next().is_some_and(|index| {
+            let (index, attempt) = index.split_once('.').unwrap_or((index, "0"));
+            digits(index) && digits(attempt)
+        })
+        && parts.next().is_some_and(|name| !name.is_empty())
+}
+
+// Not cryptographic, just to tell apart files of the same size that differ.
+fn content_hash(path: &Path) -> std::io::Result<u64> {
+    use std::hash::Hasher;
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    let mut buffer = [0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(hasher.finish()),
+            n => hasher.write(&buffer[..n]),
+        }
+    }
+}
+
+fn read_entries(dir: &Path, opts: &Options) -> Result<Vec<std::fs::DirEntry>, AppError> {
+    let io_error = |source| AppError::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+
+    // if the given path is not readable (i.e. non-directory, lack of permissions) then ignore,
+    // unless we are told to be strict about it.
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(source) if opts.strict => return Err(io_error(source)),
+        Err(_) => return Ok(vec![]),
+    };
+
+    let mut entries: Vec<std::fs::DirEntry> = vec![];
+
+    for entry in read_dir {
+        match entry {
+            Ok(ent) => entries.push(ent),
+            Err(source) if opts.strict => return Err(io_error(source)),
+            Err(_) => {}
+        }
+    }
+
+    // read_dir yields entries in whatever order the file system keeps them, so sort them to make
+    // the result (and the files picked within MAX_FILES) predictable.
+    entries.sort_by_key(|ent| ent.file_name());
+
+    Ok(entries)
+}
+
+// Every argument has to be a readable file or directory.
+fn validate_arguments(items: &[PathBuf]) -> Result<(), AppError> {
+    let problems: Vec<(PathBuf, String)> = items
+        .iter()
+        .filter_map(|item| {
+            let problem = match std::fs::metadata(item) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => "does not exist".to_string(),
+                Err(e) => e.to_string(),
+                Ok(metadata) if metadata.is_file() => match std::fs::File::open(item) {
+                    Ok(_) => return None,
+                    Err(e) => format!("is not readable ({})", e),
+                },
+                Ok(metadata) if metadata.is_dir() => match std::fs::read_dir(item) {
+                    Ok(_) => return None,
+                    Err(e) => format!("is not readable ({})", e),
+                },
+                Ok(_) => "is neither a file nor a directory".to_string(),
+            };
+
+            Some((item.clone(), problem))
+        })
+        .collect();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::InvalidArguments(problems))
+    }
+}
+
+// The arguments that exist, in order, as they are: neither made absolute nor canonicalized.
+// A missing argument is most likely a typo, so it is left out with a warning (--strict reports
+// it before we get here).
+fn existing_arguments(items: Vec<PathBuf>, warnings: &mut Vec<Warning>) -> Vec<PathBuf> {
+    items
+        .into_iter()
+        .filter_map(|p| {
+            // In Windows environment, .canonicalize() returns an abs path with a special prefix \\?\ to express extended-length path.
+            // But seemingly this kind of path doens't work properly for gvim so I don't adopt this method.
+            // match p.canonicalize() {
+            //     Ok(abs_p) => Some(abs_p),
+            //     Err(_) => None
+            // }
+
+            // We decided not to manipulate specified paths.
+            if p.exists() {
+                Some(p)
+            } else {
+                warnings.push(Warning::MissingArgument(p));
+                None
+            }
+        })
+        .collect()
+}
+
+// Expand {start..end} and {start..end..step} ranges for shells that don't, keeping only the members
+// that exist. Other arguments are left alone. No more than `limit` arguments come out.
+fn expand_range_arguments(
+    items: Vec<PathBuf>,
+    limit: usize,
+    warnings: &mut Vec<Warning>,
+) -> Vec<PathBuf> {
+    let mut result = vec![];
+    // Members that don't exist count too, the way count_arguments counts them.
+    let mut left = limit;
+
+    for item in items {
+        if left == 0 {
+            break;
+        }
+
+        let Some(members) = item
+            .to_str()
+            .and_then(|pattern| expand_ranges(pattern, left))
+        else {
+            result.push(item);
+            left -= 1;
+            continue;
+        };
+        left -= members.len();
+
+        let mut missing = vec![];
+        for member in members.into_iter().map(PathBuf::from) {
+            if member.exists() {
+                result.push(member);
+            } else {
+                missing.push(member);
+            }
+        }
+
+        if let Some(first) = missing.first() {
+            warnings.push(Warning::MissingRangeMembers {
+                range: item.clone(),
+                missing: missing.len(),
+                first: first.clone(),
+            });
+        }
+    }
+
+    result
+}
+
+// How many arguments there are once the ranges are expanded, without expanding them.
+fn count_arguments(items: &[PathBuf]) -> usize {
+    items
+        .iter()
+        .map(|item| item.to_str().and_then(count_range_members).unwrap_or(1))
+        .fold(0, usize::saturating_add)
+}
+
+// Returns None unless the pattern contains a numeric range, like expand_ranges.
+fn count_range_members(pattern: &str) -> Option<usize> {
+    let (_, spec, tail) = find_range(pattern)?;
+    let (from, to, step, _) = range_bounds(spec)?;
+    let members = usize::try_from(from.abs_diff(to) / step + 1).unwrap_or(usize::MAX);
+
+    Some(members.saturating_mul(count_range_members(tail).unwrap_or(1)))
+}
+
+// Returns None unless the pattern contains a numeric range. Only the first `limit` members are
+// built.
+fn expand_ranges(pattern: &str, limit: usize) -> Option<Vec<String>> {
+    let (head, spec, tail) = find_range(pattern)?;
+    let numbers = parse_range(spec, limit)?;
+    // Ranges in the rest of the pattern multiply the members.
+    let tails = expand_ranges(tail, limit).unwrap_or_else(|| vec![tail.to_string()]);
+
+    Some(
+        numbers
+            .iter()
+            .flat_map(|n| tails.iter().map(move |t| format!("{}{}{}", head, n, t)))
+            .take(limit)
+            .collect(),
+    )
+}
+
+// What precedes the first {...} that is a range, the inside of it, and what follows it.
+fn find_range(pattern: &str) -> Option<(&str, &str, &str)> {
+    let mut search_from = 0;
+
+    while let Some(open) = pattern[search_from..].find('{').map(|i| i + search_from) {
+        let close = pattern[open..].find('}').map(|i| i + open)?;
+
+        let spec = &pattern[open + 1..close];
+        if range_bounds(spec).is_some() {
+            return Some((&pattern[..open], spec, &pattern[close + 1..]));
+        }
+
+        search_from = open + 1;
+    }
+
+    None
+}
+
+// "1..9", "9..1" or "0..20..5", as the start, end, step and the width to pad members to.
+// Leading zeros pad every member to the same width, like bash.
+fn range_bounds(spec: &str) -> Option<(i64, i64, u64, usize)> {
+    let parts: Vec<&str> = spec.split("..").collect();
+
+    let (start, end, step) = match parts.as_slice() {
+        [start, end] => (*start, *end, "1"),
+        [start, end, step] => (*start, *end, *step),
+        _ => return None,
+    };
+
+    let from: i64 = start.parse().ok()?;
+    let to: i64 = end.parse().ok()?;
+    let step = step.parse::<i64>().ok()?.unsigned_abs().max(1);
+
+    let padded =
+        |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+    let width = if padded(start) || padded(end) {
+        start.len().max(end.len())
+    } else {
+        0
+    };
+
+    Some((from, to, step, width))
+}
+
+fn parse_range(spec: &str, limit: usize) -> Option<Vec<String>> {
+    let (from, to, step, width) = range_bounds(spec)?;
+    let step = usize::try_from(step).unwrap_or(usize::MAX);
+
+    let numbers: Vec<i64> = if from <= to {
+        (from..=to).step_by(step).take(limit).collect()
+    } else {
+        (to..=from).rev().step_by(step).take(limit).collect()
+    };
+
+    Some(
+        numbers
+            .iter()
+            .map(|n| format!("{:0width$}", n, width = width))
+            .collect(),
+    )
+}
+
+// Outside a git repository {repo} and {branch} are empty, and so is {branch} on a detached HEAD.
+// A name left with nothing but separators is no name at all.
+fn expand_server_name_template(template: &str, cwd: &Path) -> Option<String> {
+    let repo = git_root(cwd);
+    let file_name = |path: &Path| {
+        path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let name = template
+        .replace(
+            "{repo}",
+            &repo.as_deref().map(file_name).unwrap_or_default(),
+        )
+        .replace(
+            "{branch}",
+            &repo.as_deref().and_then(git_branch).unwrap_or_default(),
+        )
+        .replace("{cwd}", &file_name(cwd));
+
+    if name.chars().all(|c| !c.is_alphanumeric()) {
+        return None;
+    }
+
+    Some(name)
+}
+
+fn git_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+// Read HEAD directly rather than running git, which may not even be installed.
+fn git_branch(root: &Path) -> Option<String> {
+    let mut git_dir = root.join(".git");
+
+    // Worktrees and submodules have a file pointing to the real git directory.
+    if git_dir.is_file() {
+        let content = std::fs::read_to_string(&git_dir).ok()?;
+        git_dir = root.join(content.trim().strip_prefix("gitdir:")?.trim());
+    }
+
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+
+    head.trim()
+        .strip_prefix("ref: refs/heads/")
+        .map(String::from)
+}
+
+// The files changed between the commit and the working tree. Unlike reading HEAD, this takes
+// git itself. It names the files relative to the root of the repository.
+fn changed_since(
+    runner: &mut dyn Runner,
+    reference: &str,
+    root: &Path,
+) -> Result<Vec<PathBuf>, AppError> {
+    let invalid =
+        || AppError::InvalidOptionValue("--since-commit".to_string(), reference.to_string());
+
+    // Anything starting with a dash would be taken for an option of git diff.
+    if reference.is_empty() || reference.starts_with('-') {
+        return Err(invalid());
+    }
+
+    let mut command = Command::new("git");
+    command
+        .arg("-C")
+        .arg(root)
+        .args(["diff", "--name-only", reference, "--"]);
+
+    let output = runner
+        .output(command)
+        .map_err(|_| AppError::OpenerNotFound("git".to_string()))?;
+
+    if !output.success {
+        return Err(invalid());
+    }
+
+    Ok(output
+        .stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| root.join(line))
+        .collect())
+}
+
+// `@file` stands for the paths listed in the file, one per line, like --save-list writes them.
+// Anything else starting with @, say a file named so, stays as it is.
+fn expand_list_files(items: Vec<PathBuf>) -> Result<Vec<PathBuf>, AppError> {
+    let mut expanded = vec![];
+
+    for item in items {
+        let list = match item.to_str().and_then(|item| item.strip_prefix('@')) {
+            Some(list) if Path::new(list).is_file() => PathBuf::from(list),
+            _ => {
+                expanded.push(item);
+                continue;
+            }
+        };
+
+        let content =
+            std::fs::read_to_string(&list).map_err(|source| AppError::Io { path: list, source })?;
+
+        expanded.extend(
+            content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(PathBuf::from),
+        );
+    }
+
+    Ok(expanded)
+}
+
+// Unlike newlines, NUL can't be part of a path, so this is the only safe way to receive arbitrary
+// paths through a pipe.
+fn split_null_separated(input: &[u8]) -> Vec<PathBuf> {
+    input
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// Parse an exported quickfix (or location) list, which has a "file|line col N|text" entry per
+// line. Entries without a line number are opened at the top.
+fn parse_quickfix(content: &str) -> Vec<(PathBuf, Option<usize>)> {
+    content
+        .lines()
+        .filter_map(|entry| {
+            let mut fields = entry.splitn(3, '|');
+            let file = fields.next()?.trim();
+
+            if file.is_empty() {
+                return None;
+            }
+
+            let line = fields
+                .next()
+                .and_then(|location| location.split_whitespace().next())
+                .and_then(|line| line.parse().ok());
+
+            Some((PathBuf::from(file), line))
+        })
+        .collect()
+}
+
+fn sort_files(files: &mut [PathBuf], key: SortKey) {
+    match key {
+        SortKey::Mtime => files.sort_by_cached_key(|f| {
+            let mtime = std::fs::metadata(f).and_then(|m| m.modified());
+            std::cmp::Reverse(mtime.unwrap_or(std::time::SystemTime::UNIX_EPOCH))
+        }),
+        SortKey::Name => files.sort(),
+        SortKey::Size => {
+            files.sort_by_cached_key(|f| std::fs::metadata(f).map(|m| m.len()).unwrap_or(0))
+        }
+    }
+}
+
+// How well the query matches a path, if its characters appear in it in order (ignoring case).
+// A match in the file name beats one spread over the directories. Characters that follow the
+// previous match, and ones at the start of a word, count extra.
+fn fuzzy_score(query: &str, path: &Path) -> Option<i64> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+
+    subsequence_score(query, &name)
+        .map(|score| score + 100)
+        .or_else(|| subsequence_score(query, &path.to_string_lossy()))
+}
+
+fn subsequence_score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+
+    for wanted in query.to_lowercase().chars() {
+        let at = next + text[next..].iter().position(|&c| c == wanted)?;
+
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == at) {
+            score += 5;
+        }
+        if at == 0 || matches!(text[at - 1], '/' | '\\' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+
+        previous = Some(at);
+        next = at + 1;
+    }
+
+    Some(score)
+}
+
+// The files matching the query, best first. Of two equally good matches the shorter file name
+// wins, then the order they came in. The rank is what tells them apart.
+fn rank_fuzzy_matches(query: &str, files: &[PathBuf]) -> Vec<((i64, usize), PathBuf)> {
+    let mut ranked: Vec<((i64, usize), PathBuf)> = files
+        .iter()
+        .filter_map(|file| {
+            let length = file.file_name().map_or(0, |name| name.len());
+            let score = fuzzy_score(query, file)?;
+            Some(((-score, length), file.clone()))
+        })
+        .collect();
+
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked
+}
+
+// Ask which of the files to take; None for an answer that isn't one of their numbers.
+fn choose(
+    files: &[PathBuf],
+    input: &mut impl std::io::BufRead,
+    output: &mut impl std::io::Write,
+) -> Option<PathBuf> {
+    for (i, file) in files.iter().enumerate() {
+        let _ = writeln!(output, "{:>3}) {}", i + 1, file.display());
+    }
+    let _ = write!(output, "open which one? [1-{}] ", files.len());
+    let _ = output.flush();
+
+    let mut answer = String::new();
+    input.read_line(&mut answer).ok()?;
+
+    let n: usize = answer.trim().parse().ok()?;
+    files.get(n.checked_sub(1)?).cloned()
+}
+
+// Ask which of the files to take, by their numbers separated with commas or spaces. They keep
+// their order; nothing is taken for an answer with anything but their numbers.
+fn select(
+    files: &[PathBuf],
+    input: &mut impl std::io::BufRead,
+    output: &mut impl std::io::Write,
+) -> Vec<PathBuf> {
+    for (i, file) in files.iter().enumerate() {
+        let _ = writeln!(output, "{:>3}) {}", i + 1, file.display());
+    }
+    let _ = write!(output, "open which ones? (e.g. 1,3 4) ");
+    let _ = output.flush();
+
+    let mut answer = String::new();
+    if input.read_line(&mut answer).is_err() {
+        return vec![];
+    }
+
+    match parse_selection(&answer, files.len()) {
+        Some(chosen) => files
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| chosen.contains(&(i + 1)))
+            .map(|(_, file)| file.clone())
+            .collect(),
+        None => vec![],
+    }
+}
+
+// The numbers, from 1 to count, in the answer to select.
+fn parse_selection(answer: &str, count: usize) -> Option<Vec<usize>> {
+    answer
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.parse().ok().filter(|n| (1..=count).contains(n)))
+        .collect()
+}
+
+// Move the files of each directory next to each other. Directories keep the order in which
+// they first appear and so do the files within each of them.
+fn group_by_dir(files: &mut [PathBuf]) {
+    let mut order: HashMap<PathBuf, usize> = HashMap::new();
+
+    for f in files.iter() {
+        let next = order.len();
+        order.entry(parent_dir(f)).or_insert(next);
+    }
+
+    files.sort_by_key(|f| order[&parent_dir(f)]);
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn main() {
+    let mut app = App::new();
+    app.run();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Mutex};
+
+    // A fresh directory under the system temp dir, removed beforehand if a previous run left it.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gvi-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Records the arguments of every command instead of launching anything.
+    #[derive(Clone, Default)]
+    struct RecordingRunner {
+        calls: Arc<Mutex<Vec<Vec<String>>>>,
+        // What commands run for their output print; a full-featured gvim by default.
+        reply: Option<fn(&[String]) -> CommandOutput>,
+    }
+
+    impl RecordingRunner {
+        fn replying(reply: fn(&[String]) -> CommandOutput) -> Self {
+            RecordingRunner {
+                reply: Some(reply),
+                ..RecordingRunner::default()
+            }
+        }
+
+        fn calls(&self) -> Vec<Vec<String>> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn record(&self, command: &Command) -> Vec<String> {
+            let args: Vec<String> = command
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            self.calls.lock().unwrap().push(args.clone());
+            args
+        }
+    }
+
+    impl Runner for RecordingRunner {
+        fn spawn(&mut self, command: Command) -> std::io::Result<()> {
+            self.record(&command);
+            Ok(())
+        }
+
+        fn output(&mut self, command: Command) -> std::io::Result<CommandOutput> {
+            let args = self.record(&command);
+
+            Ok(match self.reply {
+                Some(reply) => reply(&args),
+                // A fresh instance registers right away.
+                None if args == ["--serverlist"] => stdout("GVIM"),
+                None => stdout("+clientserver"),
+            })
+        }
+    }
+
+    // Reports a gvim which has been up for the given seconds, or none.
+    struct MockDetector(Option<u64>);
+
+    impl InstanceDetector for MockDetector {
+        fn find_server(&self, name: &str) -> Option<detect::ServerInfo> {
+            self.0.map(|running_time| detect::ServerInfo {
+                name: name.to_string(),
+                running_time: Some(running_time),
+                registered: false,
+            })
+        }
+    }
+
+    // Reports a long running gvim registered under the given name, whatever we ask for.
+    struct RegisteredAs(&'static str);
+
+    impl InstanceDetector for RegisteredAs {
+        fn find_server(&self, _: &str) -> Option<detect::ServerInfo> {
+            Some(detect::ServerInfo {
+                name: self.0.to_string(),
+                running_time: Some(60),
+                registered: true,
+            })
+        }
+    }
+
+    // Time only passes when slept, and the sleeps are recorded.
+    #[derive(Clone)]
+    struct MockClock(Arc<Mutex<(Instant, Vec<Duration>)>>);
+
+    impl MockClock {
+        fn new() -> Self {
+            MockClock(Arc::new(Mutex::new((Instant::now(), vec![]))))
+        }
+
+        fn sleeps(&self) -> Vec<Duration> {
+            self.0.lock().unwrap().1.clone()
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.0.lock().unwrap().0
+        }
+
+        fn sleep(&self, duration: Duration) {
+            let (now, sleeps) = &mut *self.0.lock().unwrap();
+            *now += duration;
+            sleeps.push(duration);
+        }
+    }
+
+    // Records the thread it's asked on, and answers like MockDetector(Some(60)).
+    struct ThreadDetector(Arc<Mutex<Vec<std::thread::ThreadId>>>);
+
+    impl InstanceDetector for ThreadDetector {
+        fn find_server(&self, name: &str) -> Option<detect::ServerInfo> {
+            self.0.lock().unwrap().push(std::thread::current().id());
+            MockDetector(Some(60)).find_server(name)
+        }
+    }
+
+    struct PanickingDetector;
+
+    impl InstanceDetector for PanickingDetector {
+        fn find_server(&self, _: &str) -> Option<detect::ServerInfo> {
+            panic!("the process table must not be scanned")
+        }
+    }
+
+    fn stdout(text: &str) -> CommandOutput {
+        CommandOutput {
+            success: true,
+            stdout: text.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    #[test]
+    fn fail_to_open_large_file() {
+        let mut app = App::new();
+        app.files = vec![PathBuf::from("tests/test_asset/huge_file.txt")];
+        assert!(app.has_large_size_of_files().unwrap());
+    }
+
+    #[test]
+    fn success_to_open_large_file() {
+        let mut app = App::new();
+        app.files = vec![PathBuf::from("tests/test_asset/huge_file_but_ok.txt")];
+        assert!(!app.has_large_size_of_files().unwrap());
+    }
+
+    #[test]
+    fn detailed_limit_messages_list_what_went_over() {
+        let mut app = App::new();
+        app.files = vec![PathBuf::from("tests/test_asset/huge_file.txt")];
+        let size = std::fs::metadata(&app.files[0]).unwrap().len();
+
+        assert_eq!(
+            app.too_large_message(),
+            "Nothing has been opened, because the files add up to more than 307200 bytes."
+        );
+
+        (app.opts, _) =
+            Options::parse(&["--limit-message-detail", "detailed"].map(String::from)).unwrap();
+
+        assert_eq!(
+            app.too_large_message(),
+            format!(
+                "Nothing has been opened, because the files add up to more than 307200 bytes.\n  \
+                 tests/test_asset/huge_file.txt: {} bytes",
+                size
+            )
+        );
+
+        let items = [PathBuf::from("a"), PathBuf::from("b")];
+        app.opts.limits.max_args = 1;
+
+        assert_eq!(
+            app.too_many_arguments(&items, 2).to_string(),
+            "Nothing has been opened, because there are 2 arguments (at most 1).\n  a\n  b"
+        );
+    }
+
+    #[test]
+    fn fast_path_skips_process_scan() {
+        let mut gvim = Gvim {
+            detector: Box::new(PanickingDetector),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--fast".to_string()]).unwrap();
+        let files = vec![PathBuf::from("tests/test_asset/huge_file_but_ok.txt")];
+        assert_eq!(gvim.instance(&files, &opts, &mut vec![]), Instance::Fresh);
+    }
+
+    #[test]
+    fn editor_path_bypasses_path_lookup() {
+        let args = ["--editor-path", "tests/test_asset/fake_gvim"].map(String::from);
+        let (opts, _) = Options::parse(&args).unwrap();
+        let gvim = Gvim {
+            editor: Gvim::resolve_editor(&opts, |_| None, |_| None).unwrap(),
+            ..Gvim::default()
+        };
+        let command = gvim.command([""; 0], ["a.txt"]);
+        assert_eq!(command.get_program(), "tests/test_asset/fake_gvim");
+    }
+
+    #[test]
+    fn editor_path_from_env() {
+        let (opts, _) = Options::parse(&[]).unwrap();
+        let editor = Gvim::resolve_editor(
+            &opts,
+            |key| (key == "GVI_EDITOR_PATH").then(|| "tests/test_asset/fake_gvim".to_string()),
+            |_| None,
+        );
+        assert_eq!(editor.unwrap(), PathBuf::from("tests/test_asset/fake_gvim"));
+    }
+
+    #[test]
+    fn editor_is_the_first_one_installed() {
+        let (opts, _) = Options::parse(&[]).unwrap();
+        let installed = |names: &'static [&'static str]| {
+            move |name: &str| {
+                names
+                    .contains(&name)
+                    .then(|| PathBuf::from("/usr/bin").join(name))
+            }
+        };
+
+        assert_eq!(
+            Gvim::resolve_editor(&opts, |_| None, installed(&["nvim-qt", "mvim", "gvim"])).unwrap(),
+            PathBuf::from("/usr/bin/gvim")
+        );
+        assert_eq!(
+            Gvim::resolve_editor(&opts, |_| None, installed(&["nvim-qt", "mvim"])).unwrap(),
+            PathBuf::from("/usr/bin/mvim")
+        );
+        assert_eq!(
+            Gvim::resolve_editor(&opts, |_| None, installed(&["nvim-qt"])).unwrap(),
+            PathBuf::from("/usr/bin/nvim-qt")
+        );
+        assert!(matches!(
+            Gvim::resolve_editor(&opts, |_| None, installed(&[])),
+            Err(AppError::EditorNotFound)
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn editor_path_must_be_executable() {
+        let args = ["--editor-path=tests/test_asset/huge_file.txt"].map(String::from);
+        let (opts, _) = Options::parse(&args).unwrap();
+        assert!(matches!(
+            Gvim::resolve_editor(&opts, |_| None, |_| None),
+            Err(AppError::EditorNotExecutable(_))
+        ));
+    }
+
+    #[test]
+    fn buffer_mode_uses_plain_remote() {
+        let (opts, _) = Options::parse(&["--buffer".to_string()]).unwrap();
+        assert_eq!(Gvim::default().reuse_instance_options(&opts)[2], "--remote");
+
+        let (opts, _) = Options::parse(&[]).unwrap();
+        assert_eq!(
+            Gvim::default().reuse_instance_options(&opts)[2],
+            "--remote-tab"
+        );
+    }
+
+    #[test]
+    fn long_file_lists_are_split_into_several_invocations() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            arg_budget: 64,
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let files: Vec<PathBuf> = (0..20)
+            .map(|i| PathBuf::from(format!("dir/file_{:02}.txt", i)))
+            .collect();
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        gvim.open(&files, &opts).unwrap();
+
+        let calls = runner.calls();
+        assert!(calls.len() > 1);
+
+        let opened: Vec<String> = calls
+            .iter()
+            .inspect(|args| assert_eq!(args[..3], ["--servername", "GVIM", "--remote-tab"]))
+            .flat_map(|args| args[3..].to_vec())
+            .collect();
+        let expected: Vec<String> = files.iter().map(|f| f.display().to_string()).collect();
+        assert_eq!(opened, expected);
+    }
+
+    fn sort_fixture() -> Vec<PathBuf> {
+        let dir = temp_dir("sort");
+        let now = std::time::SystemTime::now();
+        // (name, size, age in seconds)
+        [("b.txt", 30, 300), ("a.txt", 20, 100), ("c.txt", 10, 200)]
+            .iter()
+            .map(|(name, size, age)| {
+                let path = dir.join(name);
+                let file = std::fs::File::create(&path).unwrap();
+                file.set_len(*size).unwrap();
+                file.set_modified(now - std::time::Duration::from_secs(*age))
+                    .unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn names(files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn sort_by_each_key() {
+        let mut files = sort_fixture();
+
+        sort_files(&mut files, SortKey::Name);
+        assert_eq!(names(&files), ["a.txt", "b.txt", "c.txt"]);
+
+        sort_files(&mut files, SortKey::Mtime);
+        assert_eq!(names(&files), ["a.txt", "c.txt", "b.txt"]);
+
+        sort_files(&mut files, SortKey::Size);
+        assert_eq!(names(&files), ["c.txt", "a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn sort_by_rejects_unknown_key() {
+        let args = ["--sort-by", "color"].map(String::from);
+        assert!(matches!(
+            Options::parse(&args),
+            Err(AppError::InvalidOptionValue(_, _))
+        ));
+    }
+
+    #[test]
+    fn unreadable_paths_are_skipped_unless_strict() {
+        let missing = PathBuf::from("tests/test_asset/no_such_dir");
+
+        let (lenient, _) = Options::parse(&[]).unwrap();
+        assert!(
+            expand_dir(missing.clone(), 100, MAX_SIZE, &lenient, &RealFs)
+                .unwrap()
+                .files
+                .is_empty()
+        );
+
+        let (strict, _) = Options::parse(&["--strict".to_string()]).unwrap();
+        match expand_dir(missing.clone(), 100, MAX_SIZE, &strict, &RealFs) {
+            Err(AppError::Io { path, .. }) => assert_eq!(path, missing),
+            _ => panic!("expected an io error for {}", missing.display()),
+        }
+    }
+
+    #[test]
+    fn strict_size_check_reports_the_failing_path() {
+        let mut app = App::new();
+        app.files = vec![PathBuf::from("tests/test_asset/no_such_file.txt")];
+        assert!(!app.has_large_size_of_files().unwrap());
+
+        app.opts.strict = true;
+        match app.has_large_size_of_files() {
+            Err(AppError::Io { path, .. }) => {
+                assert_eq!(path, PathBuf::from("tests/test_asset/no_such_file.txt"))
+            }
+            _ => panic!("expected an io error"),
+        }
+    }
+
+    #[test]
+    fn error_messages() {
+        let io = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let cases = [
+            (
+                AppError::MissingOptionValue("--sort-by".to_string()),
+                "Option --sort-by requires a value.",
+            ),
+            (
+                AppError::InvalidOptionValue("--sort-by".to_string(), "color".to_string()),
+                "Invalid value 'color' for option --sort-by.",
+            ),
+            (
+                AppError::EditorNotFound,
+                "It seems you don't have gvim executable.",
+            ),
+            (
+                AppError::EditorNotExecutable(PathBuf::from("a.txt")),
+                "a.txt is not executable.",
+            ),
+            (
+                AppError::Io {
+                    path: PathBuf::from("src"),
+                    source: io(),
+                },
+                "Failed to read src: entity not found",
+            ),
+            (
+                AppError::CommandSpawnError(io()),
+                "Failed to launch gvim: entity not found",
+            ),
+        ];
+
+        for (err, message) in cases {
+            assert_eq!(err.to_string(), message);
+        }
+
+        let err = AppError::Io {
+            path: PathBuf::from("src"),
+            source: io(),
+        };
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn confirmation_follows_the_answer() {
+        let mut app = App::new();
+        app.files = vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+
+        let mut prompt = vec![];
+        assert!(app.confirm(&mut "y\n".as_bytes(), &mut prompt));
+        assert_eq!(
+            String::from_utf8(prompt).unwrap(),
+            "about to open 2 files, continue? [y/N] "
+        );
+
+        assert!(!app.confirm(&mut "n\n".as_bytes(), &mut vec![]));
+        assert!(!app.confirm(&mut "\n".as_bytes(), &mut vec![]));
+    }
+
+    #[test]
+    fn fuzzy_matches_prefer_whole_words_in_short_names() {
+        let files: Vec<PathBuf> = [
+            "src/domain.rs",
+            "src/cli/main_window.rs",
+            "README.md",
+            "src/maintenance.rs",
+            "src/main.rs",
+            "tests/main.rs",
+        ]
+        .map(PathBuf::from)
+        .to_vec();
+        let best = |query: &str| -> Vec<PathBuf> {
+            rank_fuzzy_matches(query, &files)
+                .into_iter()
+                .map(|(_, file)| file)
+                .collect()
+        };
+
+        assert_eq!(
+            best("main"),
+            [
+                "src/main.rs",
+                "tests/main.rs",
+                "src/cli/main_window.rs",
+                "src/maintenance.rs",
+                "src/domain.rs",
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(best("MW"), [PathBuf::from("src/cli/main_window.rs")]);
+        assert_eq!(best("clirs"), [PathBuf::from("src/cli/main_window.rs")]);
+        assert!(best("xyz").is_empty());
+
+        let mut app = App::new();
+        app.files = files.clone();
+        (app.opts, _) = Options::parse(&["--fuzzy-n", "3"].map(String::from)).unwrap();
+        assert!(app.pick_fuzzy_matches("main", &mut "".as_bytes(), &mut vec![]));
+        assert_eq!(
+            app.files,
+            ["src/main.rs", "tests/main.rs", "src/cli/main_window.rs"].map(PathBuf::from)
+        );
+        assert!(Options::parse(&["--fuzzy-n", "0"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn selection_takes_the_files_by_number() {
+        let files = ["a.txt", "b.txt", "c.txt", "d.txt"].map(PathBuf::from);
+
+        let mut prompt = vec![];
+        assert_eq!(
+            select(&files, &mut "4, 2 2\n".as_bytes(), &mut prompt),
+            ["b.txt", "d.txt"].map(PathBuf::from)
+        );
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert!(prompt.starts_with("  1) a.txt\n  2) b.txt\n"));
+
+        assert!(select(&files, &mut "1,5\n".as_bytes(), &mut vec![]).is_empty());
+        assert!(select(&files, &mut "all\n".as_bytes(), &mut vec![]).is_empty());
+        assert!(select(&files, &mut "\n".as_bytes(), &mut vec![]).is_empty());
+        assert_eq!(parse_selection("1,,3", 3), Some(vec![1, 3]));
+        assert_eq!(parse_selection("0", 3), None);
+    }
+
+    #[test]
+    fn tied_fuzzy_matches_are_chosen_by_number() {
+        let tied = ["src/main.rs", "tests/main.rs"].map(PathBuf::from);
+
+        let mut prompt = vec![];
+        assert_eq!(
+            choose(&tied, &mut "2\n".as_bytes(), &mut prompt),
+            Some(PathBuf::from("tests/main.rs"))
+        );
+        assert_eq!(
+            String::from_utf8(prompt).unwrap(),
+            "  1) src/main.rs\n  2) tests/main.rs\nopen which one? [1-2] "
+        );
+
+        assert_eq!(choose(&tied, &mut "0\n".as_bytes(), &mut vec![]), None);
+        assert_eq!(choose(&tied, &mut "\n".as_bytes(), &mut vec![]), None);
+    }
+
+    #[test]
+    fn yes_skips_confirmation() {
+        let mut app = App::new();
+        app.files = vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+        (app.opts, _) = Options::parse(&["--confirm", "1", "-y"].map(String::from)).unwrap();
+        assert!(!app.needs_confirmation());
+    }
+
+    #[test]
+    fn quickfix_entries() {
+        let content = std::fs::read_to_string("tests/test_asset/quickfix.txt").unwrap();
+        assert_eq!(
+            parse_quickfix(&content),
+            [
+                (PathBuf::from("src/main.rs"), Some(42)),
+                (PathBuf::from("README.md"), Some(7)),
+                (PathBuf::from("build.rs"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn manifest_entries() {
+        let content = std::fs::read_to_string("tests/test_asset/manifest.json").unwrap();
+        assert_eq!(
+            manifest::parse(&content).unwrap(),
+            [
+                (PathBuf::from("src/main.rs"), Some(42)),
+                (PathBuf::from("README.md"), None),
+                (PathBuf::from("docs/café \"notes\".md"), Some(7)),
+            ]
+        );
+        assert_eq!(manifest::parse(" [ ] ").unwrap(), []);
+
+        for (manifest, error) in [
+            (
+                "{\"path\": \"a.rs\"}",
+                "invalid type: map, expected a sequence at line 1 column 0",
+            ),
+            (
+                "[{\"path\": \"a.rs\"},\n{\"line\": 3}]",
+                "missing field `path` at line 2 column 11",
+            ),
+            (
+                "[{\"path\": \"a.rs\", \"line\": 0}]",
+                "invalid value: integer `0`, expected a nonzero usize at line 1 column 27",
+            ),
+            (
+                "[{\"path\": \"a.rs\", \"line\": 1.5}]",
+                "invalid type: floating point `1.5`, expected a nonzero usize at line 1 column 29",
+            ),
+            (
+                "[{\"path\": \"a.rs\"}",
+                "EOF while parsing a list at line 1 column 17",
+            ),
+            ("[] []", "trailing characters at line 1 column 4"),
+            ("[{\"path\": \"\"}]", "an entry has an empty \"path\""),
+        ] {
+            assert_eq!(manifest::parse(manifest).unwrap_err(), error);
+        }
+    }
+
+    #[test]
+    fn quickfix_lines_reach_gvim() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        gvim.clientserver = Some(true);
+        let (mut opts, _) = Options::parse(&[]).unwrap();
+        opts.lines.insert(PathBuf::from("src/main.rs"), 42);
+
+        let files = [PathBuf::from("build.rs"), PathBuf::from("src/main.rs")];
+        gvim.open(&files, &opts).unwrap();
+
+        let calls = runner.calls();
+        assert_eq!(calls[0][3..], ["build.rs"]);
+        assert_eq!(calls[1][3..], ["+42", "src/main.rs"]);
+    }
+
+    #[test]
+    fn a_line_directive_applies_to_the_next_file() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        gvim.clientserver = Some(true);
+        let args = ["build.rs", "+42", "file.txt"].map(String::from);
+        let (opts, items) = Options::parse(&args).unwrap();
+
+        assert_eq!(items, ["build.rs", "file.txt"]);
+        assert_eq!(opts.lines.get(Path::new("file.txt")), Some(&42));
+        assert!(opts.given.is_empty());
+
+        let files = [PathBuf::from("build.rs"), PathBuf::from("file.txt")];
+        gvim.open(&files, &opts).unwrap();
+
+        let calls = runner.calls();
+        assert_eq!(calls[0][3..], ["build.rs"]);
+        assert_eq!(calls[1][3..], ["+42", "file.txt"]);
+    }
+
+    #[test]
+    fn file_change_triggers_checktime() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let files = [PathBuf::from("report.html")];
+        let before = SystemTime::UNIX_EPOCH;
+        let after = before + Duration::from_secs(1);
+        let mut watcher = Watcher::new(&files, |_| Some(before));
+
+        assert!(
+            !gvim
+                .reload_if_changed(&mut watcher, |_| Some(before))
+                .unwrap()
+        );
+        assert!(runner.calls().is_empty());
+
+        assert!(
+            gvim.reload_if_changed(&mut watcher, |_| Some(after))
+                .unwrap()
+        );
+        assert_eq!(
+            runner.calls()[0],
+            [
+                "--servername",
+                "GVIM",
+                "--remote-send",
+                "<C-\\><C-N>:checktime<CR>"
+            ]
+        );
+    }
+
+    #[test]
+    fn files_of_a_directory_are_contiguous() {
+        let mut files: Vec<PathBuf> = ["src/a.rs", "tests/x.rs", "src/b.rs", "c.rs", "tests/y.rs"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+
+        group_by_dir(&mut files);
+
+        let expected: Vec<PathBuf> = ["src/a.rs", "src/b.rs", "tests/x.rs", "tests/y.rs", "c.rs"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(files, expected);
+    }
+
+    #[test]
+    fn missing_clientserver_falls_back_to_fresh_instances() {
+        let runner =
+            RecordingRunner::replying(|_| stdout("+clipboard -clientserver +cmdline_compl"));
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+        gvim.open(&[PathBuf::from("b.txt")], &opts).unwrap();
+
+        let calls = runner.calls();
+        assert_eq!(calls, [vec!["--version"], vec!["a.txt"], vec!["b.txt"]]);
+        assert_eq!(gvim.warnings.len(), 1);
+    }
+
+    #[test]
+    fn server_name_precedence() {
+        let project = |key: &str| (key == "GVI_PROJECT").then(|| "PROJECTX".to_string());
+
+        let (opts, _) = Options::parse(&[]).unwrap();
+        let gvim = Gvim {
+            server_name: Gvim::resolve_server_name(&opts, project),
+            ..Gvim::default()
+        };
+        assert_eq!(
+            gvim.reuse_instance_options(&opts),
+            ["--servername", "PROJECTX", "--remote-tab"]
+        );
+        assert_eq!(gvim.fresh_instance_options(), ["--servername", "PROJECTX"]);
+
+        let (opts, _) = Options::parse(&["--server-name", "MINE"].map(String::from)).unwrap();
+        assert_eq!(Gvim::resolve_server_name(&opts, project), "MINE");
+
+        let (opts, _) = Options::parse(&[]).unwrap();
+        assert_eq!(Gvim::resolve_server_name(&opts, |_| None), "GVIM");
+    }
+
+    #[test]
+    fn server_per_pane_follows_tmux() {
+        let tmux = |key: &str| match key {
+            "TMUX_PANE" => Some("%12".to_string()),
+            "GVI_PROJECT" => Some("PROJECTX".to_string()),
+            _ => None,
+        };
+
+        let (opts, _) = Options::parse(&["--server-per-pane".to_string()]).unwrap();
+        assert_eq!(Gvim::resolve_server_name(&opts, tmux), "GVIM_PANE12");
+        assert_eq!(Gvim::resolve_server_name(&opts, |_| None), "GVIM");
+
+        let (opts, _) = Options::parse(&[]).unwrap();
+        assert_eq!(Gvim::resolve_server_name(&opts, tmux), "PROJECTX");
+
+        let (opts, _) =
+            Options::parse(&["--server-per-pane", "--server-name", "MINE"].map(String::from))
+                .unwrap();
+        assert_eq!(Gvim::resolve_server_name(&opts, tmux), "MINE");
+    }
+
+    #[test]
+    fn server_name_template() {
+        let root = temp_dir("template").join("shop");
+        let cwd = root.join("web");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(&cwd).unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/feature/cart\n").unwrap();
+
+        assert_eq!(
+            expand_server_name_template("{repo}/{branch}", &cwd).unwrap(),
+            "shop/feature/cart"
+        );
+        assert_eq!(
+            expand_server_name_template("{repo}-{cwd}", &cwd).unwrap(),
+            "shop-web"
+        );
+
+        std::fs::write(root.join(".git/HEAD"), "0123abcd\n").unwrap();
+        assert_eq!(
+            expand_server_name_template("{repo}@{branch}", &cwd).unwrap(),
+            "shop@"
+        );
+
+        std::fs::remove_dir_all(root.join(".git")).unwrap();
+        assert_eq!(expand_server_name_template("{repo}/{branch}", &cwd), None);
+    }
+
+    #[test]
+    fn null_separated_paths() {
+        let input = b"a.txt\0dir/with\nnewline.txt\0\0b c.txt\0";
+        assert_eq!(
+            split_null_separated(input),
+            [
+                PathBuf::from("a.txt"),
+                PathBuf::from("dir/with\nnewline.txt"),
+                PathBuf::from("b c.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn mock_detector_drives_the_instance_choice() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&[]).unwrap();
+        let files = [PathBuf::from("a.txt")];
+
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(runner.calls().last().unwrap(), &["a.txt"]);
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls().last().unwrap(),
+            &["--servername", "GVIM", "--remote-tab", "a.txt"]
+        );
+    }
+
+    #[test]
+    fn remote_calls_use_the_registered_server_name() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(RegisteredAs("FOO")),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [["--servername", "FOO", "--remote-tab", "a.txt"]]
+        );
+    }
+
+    #[test]
+    fn numeric_ranges() {
+        assert_eq!(
+            expand_ranges("app.{1..3}.log", MAX_ARGS).unwrap(),
+            ["app.1.log", "app.2.log", "app.3.log"]
+        );
+        assert_eq!(expand_ranges("{3..1}", MAX_ARGS).unwrap(), ["3", "2", "1"]);
+        assert_eq!(
+            expand_ranges("{0..20..10}", MAX_ARGS).unwrap(),
+            ["0", "10", "20"]
+        );
+        assert_eq!(
+            expand_ranges("{08..10}", MAX_ARGS).unwrap(),
+            ["08", "09", "10"]
+        );
+        assert_eq!(expand_ranges("{a,b}.txt", MAX_ARGS), None);
+        assert_eq!(expand_ranges("plain.txt", MAX_ARGS), None);
+        assert_eq!(
+            expand_ranges("{1..9}.{1..9}", 3).unwrap(),
+            ["1.1", "1.2", "1.3"]
+        );
+    }
+
+    #[test]
+    fn ranges_are_counted_without_expanding_them() {
+        assert_eq!(count_range_members("app.{1..4}.log"), Some(4));
+        assert_eq!(count_range_members("{0..20..5}"), Some(5));
+        assert_eq!(count_range_members("{9..1..2}"), Some(5));
+        assert_eq!(count_range_members("{1..3}.{1..10}"), Some(30));
+        assert_eq!(count_range_members("plain.txt"), None);
+        assert_eq!(
+            count_range_members("{1..9223372036854775807}.{1..9223372036854775807}"),
+            Some(usize::MAX)
+        );
+    }
+
+    #[test]
+    fn a_huge_range_is_too_many_arguments() {
+        let mut app = App::new();
+
+        let result = app.limit_arguments(vec![PathBuf::from("app.{1..3000000000}.log")]);
+
+        assert!(matches!(
+            result,
+            Err(AppError::TooManyArguments {
+                count: 3000000000,
+                limit: MAX_ARGS,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn a_huge_range_is_cut_short_with_truncate_args() {
+        let dir = temp_dir("huge-range");
+        std::fs::write(dir.join("app.1.log"), "").unwrap();
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--truncate-args".to_string()]).unwrap();
+
+        let kept = app
+            .limit_arguments(vec![dir.join("app.{1..3000000000}.log")])
+            .unwrap();
+
+        assert_eq!(kept, [dir.join("app.1.log")]);
+        assert_eq!(
+            app.take_warnings(),
+            [
+                Warning::TruncatedArguments(3000000000, MAX_ARGS),
+                Warning::MissingRangeMembers {
+                    range: dir.join("app.{1..3000000000}.log"),
+                    missing: MAX_ARGS - 1,
+                    first: dir.join("app.2.log"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn range_members_must_exist() {
+        let dir = temp_dir("range");
+        for i in 1..=3 {
+            std::fs::write(dir.join(format!("app.{}.log", i)), "").unwrap();
+        }
+
+        let mut warnings = vec![];
+        let expanded =
+            expand_range_arguments(vec![dir.join("app.{1..5}.log")], MAX_ARGS, &mut warnings);
+        let expected: Vec<PathBuf> = (1..=3)
+            .map(|i| dir.join(format!("app.{}.log", i)))
+            .collect();
+        assert_eq!(expanded, expected);
+        assert_eq!(
+            warnings,
+            [Warning::MissingRangeMembers {
+                range: dir.join("app.{1..5}.log"),
+                missing: 2,
+                first: dir.join("app.4.log"),
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_arguments_are_left_out_with_a_warning() {
+        let dir = temp_dir("missing-argument");
+        std::fs::write(dir.join("main.rs"), "").unwrap();
+        let args = vec![dir.join("main.rs"), dir.join("mian.rs")];
+
+        let mut warnings = vec![];
+        assert_eq!(
+            existing_arguments(args.clone(), &mut warnings),
+            [dir.join("main.rs")]
+        );
+        assert_eq!(warnings, [Warning::MissingArgument(dir.join("mian.rs"))]);
+        assert_eq!(
+            warnings[0].to_string(),
+            format!(
+                "{} does not exist, so it is left out.",
+                dir.join("mian.rs").display()
+            )
+        );
+
+        assert!(validate_arguments(&args).is_err());
+    }
+
+    #[test]
+    fn no_recurse_keeps_top_level_files() {
+        let dir = temp_dir("no-recurse");
+        std::fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        for file in ["a.txt", "b.txt", "sub/c.txt", "sub/deeper/d.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let (opts, _) = Options::parse(&["--no-recurse".to_string()]).unwrap();
+        let files = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs)
+            .unwrap()
+            .files;
+        assert_eq!(files, [dir.join("a.txt"), dir.join("b.txt")]);
+
+        let (opts, _) = Options::parse(&[]).unwrap();
+        assert_eq!(
+            expand_dir(dir, 100, MAX_SIZE, &opts, &RealFs)
+                .unwrap()
+                .files
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn expansion_of_a_nested_tree() {
+        let dir = temp_dir("expansion");
+        std::fs::create_dir_all(dir.join("b/c")).unwrap();
+        for file in ["a.txt", "b/b.txt", "b/c/c.txt", "d.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        let expansion = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs).unwrap();
+        assert_eq!(
+            expansion,
+            Expansion {
+                files: ["a.txt", "b/b.txt", "b/c/c.txt", "d.txt"]
+                    .iter()
+                    .map(|f| dir.join(f))
+                    .collect(),
+                // the directory itself, two subdirectories and four files
+                count: 7,
+                limit_hit: None,
+                size: 0,
+                size_limit_hit: false,
+                oversized: vec![],
+                long_names: vec![],
+                dirs: 3,
+                skipped: 0,
+            }
+        );
+
+        let expansion = expand_dir(dir.clone(), 3, MAX_SIZE, &opts, &RealFs).unwrap();
+        assert_eq!(expansion.limit_hit, Some(dir.join("b")));
+        assert_eq!(expansion.files, [dir.join("a.txt")]);
+
+        let file = expand_dir(dir.join("d.txt"), 1, MAX_SIZE, &opts, &RealFs).unwrap();
+        assert_eq!((file.files.len(), file.count, file.limit_hit), (1, 1, None));
+    }
+
+    #[test]
+    fn breadth_first_order_takes_each_level_before_the_next() {
+        let dir = temp_dir("expansion-order");
+        std::fs::create_dir_all(dir.join("b/c")).unwrap();
+        std::fs::create_dir_all(dir.join("e")).unwrap();
+        for file in ["a.txt", "b/b.txt", "b/c/c.txt", "d.txt", "e/e.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let files = |order: &str| {
+            let (opts, _) = Options::parse(&["--order", order].map(String::from)).unwrap();
+            let expansion = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs).unwrap();
+            expansion
+                .files
+                .iter()
+                .map(|file| file.strip_prefix(&dir).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            files("dfs"),
+            ["a.txt", "b/b.txt", "b/c/c.txt", "d.txt", "e/e.txt"].map(PathBuf::from)
+        );
+        assert_eq!(
+            files("bfs"),
+            ["a.txt", "d.txt", "b/b.txt", "e/e.txt", "b/c/c.txt"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn entry_cap_names_the_directory_it_tripped_in() {
+        let dir = temp_dir("entry-cap");
+        std::fs::create_dir(dir.join("big")).unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        for i in 0..10 {
+            std::fs::write(dir.join(format!("big/{}.txt", i)), "").unwrap();
+        }
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--max-total", "5"].map(String::from)).unwrap();
+
+        let err = app.collect_files(vec![dir.clone()]).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Nothing has been opened, because more than 5 paths came up while expanding {} \
+                 (3 files collected by then).\nPlease break down the arguments, e.g. to the \
+                 subdirectories of it, or raise --max-total.",
+                dir.join("big").display()
+            )
+        );
+    }
+
+    #[test]
+    fn include_dirs_opens_the_directory_too() {
+        let dir = temp_dir("include-dirs");
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        std::fs::write(dir.join("b.txt"), "").unwrap();
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--include-dirs".to_string()]).unwrap();
+
+        let files = app.collect_files(vec![dir.clone()]).unwrap();
+        assert_eq!(files, [dir.clone(), dir.join("a.txt"), dir.join("b.txt")]);
+    }
+
+    #[test]
+    fn collecting_stats_each_path_once() {
+        // Counts the stat calls per path.
+        struct CountingFs(Arc<Mutex<HashMap<PathBuf, usize>>>);
+
+        impl FileSystem for CountingFs {
+            fn metadata(&self, path: &Path) -> std::io::Result<std::fs::Metadata> {
+                *self
+                    .0
+                    .lock()
+                    .unwrap()
+                    .entry(path.to_path_buf())
+                    .or_default() += 1;
+                std::fs::metadata(path)
+            }
+        }
+
+        let dir = temp_dir("stat-once");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "12345").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "123").unwrap();
+        std::fs::write(dir.join("c.txt"), "1").unwrap();
+        let stats = Arc::new(Mutex::new(HashMap::new()));
+
+        let mut app = App::new();
+        app.fs = Box::new(CountingFs(stats.clone()));
+        app.files = app
+            .collect_files(vec![dir.join("sub"), dir.join("a.txt"), dir.join("c.txt")])
+            .unwrap();
+
+        assert!(!app.has_large_size_of_files().unwrap());
+        assert_eq!(app.size, Some(9));
+        assert_eq!(app.files.len(), 3);
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.len(), 4);
+        assert!(stats.values().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn collecting_stops_once_the_files_are_too_large() {
+        let mut app = App::new();
+        let files = app
+            .collect_files(vec![
+                PathBuf::from("tests/test_asset/huge_file.txt"),
+                PathBuf::from("tests/test_asset/huge_file_but_ok.txt"),
+            ])
+            .unwrap();
+
+        assert_eq!(files, [PathBuf::from("tests/test_asset/huge_file.txt")]);
+        assert!(app.has_large_size_of_files().unwrap());
+    }
+
+    #[test]
+    fn latest_takes_the_newest_file_of_a_directory() {
+        let dir = temp_dir("latest");
+        let epoch = SystemTime::UNIX_EPOCH;
+        for (file, age) in [("a.log", 30), ("b.log", 10), ("c.txt", 0), ("d.log", 20)] {
+            let file = std::fs::File::create(dir.join(file)).unwrap();
+            file.set_modified(epoch + Duration::from_secs(1_700_000_000 - age))
+                .unwrap();
+        }
+
+        let (opts, _) = Options::parse(&["--latest".to_string()]).unwrap();
+        let expansion = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs).unwrap();
+        assert_eq!(expansion.files, [dir.join("c.txt")]);
+
+        let (opts, _) = Options::parse(&["--latest", "--ext", "log"].map(String::from)).unwrap();
+        let expansion = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs).unwrap();
+        assert_eq!(expansion.files, [dir.join("b.log")]);
+    }
+
+    #[test]
+    fn ext_filters_the_files_of_directories() {
+        let dir = temp_dir("ext");
+        for file in ["a.rs", "b.md", "c.RS", "d"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let (opts, _) = Options::parse(&["--ext=.rs,toml".to_string()]).unwrap();
+        let files = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs)
+            .unwrap()
+            .files;
+        assert_eq!(files, [dir.join("a.rs"), dir.join("c.RS")]);
+
+        let file = expand_dir(dir.join("b.md"), 100, MAX_SIZE, &opts, &RealFs).unwrap();
+        assert_eq!(file.files, [dir.join("b.md")]);
+    }
+
+    #[test]
+    fn colors_follow_the_choice_and_no_color() {
+        let no_env = |_: &str| None;
+        let no_color = |key: &str| (key == "NO_COLOR").then(|| "1".to_string());
+
+        assert!(ColorChoice::Auto.enabled(no_env, true));
+        assert!(!ColorChoice::Auto.enabled(no_env, false));
+        assert!(!ColorChoice::Auto.enabled(no_color, true));
+        assert!(!ColorChoice::Never.enabled(no_env, true));
+        assert!(ColorChoice::Always.enabled(no_env, false));
+
+        let (opts, _) = Options::parse(&["--color=never".to_string()]).unwrap();
+        let color = opts.color.enabled(no_env, true);
+        for severity in [Severity::Error, Severity::Warning, Severity::Note] {
+            assert!(!format_message(severity, "gvim is gone.", color).contains('\x1b'));
+        }
+        assert_eq!(
+            format_message(Severity::Error, "gvim is gone.", false),
+            "Error: gvim is gone."
+        );
+        assert_eq!(
+            format_message(Severity::Warning, "gvim is gone.", true),
+            "\x1b[33mWarning:\x1b[0m gvim is gone."
+        );
+        assert_eq!(
+            format_message(Severity::Note, "gvim is gone.", true),
+            "\x1b[36mNote:\x1b[0m gvim is gone."
+        );
+    }
+
+    #[test]
+    fn no_expand_refuses_directory_arguments() {
+        let dir = temp_dir("no-expand");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/a.txt"), "").unwrap();
+        std::fs::write(dir.join("b.txt"), "123").unwrap();
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--no-expand".to_string()]).unwrap();
+
+        assert!(matches!(
+            app.collect_files(vec![dir.join("b.txt"), dir.join("sub")]),
+            Err(AppError::DirectoryArgument(path)) if path == dir.join("sub")
+        ));
+
+        let files = app.collect_files(vec![dir.join("b.txt")]).unwrap();
+        assert_eq!(files, [dir.join("b.txt")]);
+        assert_eq!(app.size, Some(3));
+    }
+
+    #[test]
+    fn files_gone_before_opening_are_left_out() {
+        let dir = temp_dir("gone");
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let mut app = App::new();
+        app.files = app.collect_files(vec![dir.clone()]).unwrap();
+        std::fs::remove_file(dir.join("b.txt")).unwrap();
+        std::fs::remove_file(dir.join("c.txt")).unwrap();
+        std::fs::create_dir(dir.join("c.txt")).unwrap();
+
+        assert!(app.revalidate_files());
+        assert_eq!(app.files, [dir.join("a.txt")]);
+        assert_eq!(
+            app.take_warnings(),
+            [
+                Warning::GoneBeforeOpening(dir.join("b.txt")),
+                Warning::GoneBeforeOpening(dir.join("c.txt"))
+            ]
+        );
+
+        std::fs::remove_file(dir.join("a.txt")).unwrap();
+        assert!(!app.revalidate_files());
+    }
+
+    #[test]
+    fn duplicate_paths_are_opened_once() {
+        let dir = temp_dir("dedupe-path");
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        std::fs::write(dir.join("b.txt"), "").unwrap();
+
+        let mut app = App::new();
+        app.files = app
+            .collect_files(vec![dir.join("b.txt"), dir.clone()])
+            .unwrap();
+        app.dedupe_files();
+
+        assert_eq!(app.files, [dir.join("b.txt"), dir.join("a.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hard_links_are_opened_once_by_inode() {
+        let dir = temp_dir("dedupe-inode");
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        std::fs::hard_link(dir.join("a.txt"), dir.join("b.txt")).unwrap();
+        std::fs::write(dir.join("c.txt"), "").unwrap();
+
+        let mut app = App::new();
+        app.files = app.collect_files(vec![dir.clone()]).unwrap();
+        app.dedupe_files();
+        assert_eq!(app.files.len(), 3);
+
+        (app.opts, _) = Options::parse(&["--dedupe-by", "inode"].map(String::from)).unwrap();
+        app.dedupe_files();
+        assert_eq!(app.files, [dir.join("a.txt"), dir.join("c.txt")]);
+    }
+
+    #[test]
+    fn identical_contents_are_opened_once_by_content() {
+        let dir = temp_dir("dedupe-content");
+        std::fs::write(dir.join("a.txt"), "same").unwrap();
+        std::fs::write(dir.join("b.txt"), "diff").unwrap();
+        std::fs::write(dir.join("c.txt"), "same").unwrap();
+        std::fs::write(dir.join("d.txt"), "longer").unwrap();
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--dedupe-by", "content"].map(String::from)).unwrap();
+        app.files = app.collect_files(vec![dir.clone()]).unwrap();
+        app.dedupe_files();
+
+        assert_eq!(
+            app.files,
+            [dir.join("a.txt"), dir.join("b.txt"), dir.join("d.txt")]
+        );
+    }
+
+    // gvi never resolves paths, so :w in gvim writes through the link. Only the size and the
+    // inode come from the target.
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_reach_gvim_as_given() {
+        let dir = temp_dir("symlink");
+        std::fs::write(dir.join("target.txt"), "12345").unwrap();
+        std::os::unix::fs::symlink("target.txt", dir.join("link.txt")).unwrap();
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+
+        app.files = app.collect_files(vec![dir.join("link.txt")]).unwrap();
+        app.open().unwrap();
+
+        assert_eq!(app.size, Some(5));
+        assert_eq!(
+            runner.calls(),
+            [[dir.join("link.txt").to_string_lossy().into_owned()]]
+        );
+
+        app.files = vec![dir.join("link.txt"), dir.join("target.txt")];
+        (app.opts, _) = Options::parse(&["--dedupe-by", "inode"].map(String::from)).unwrap();
+        app.dedupe_files();
+        assert_eq!(app.files, [dir.join("link.txt")]);
+    }
+
+    #[test]
+    fn many_tabs_in_one_window_are_advised_against() {
+        let warnings = |count: usize, args: &[&str]| {
+            let mut gvim = Gvim {
+                detector: Box::new(MockDetector(Some(60))),
+                runner: Box::new(RecordingRunner::default()),
+                ..Gvim::default()
+            };
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let (opts, _) = Options::parse(&args).unwrap();
+            let files: Vec<PathBuf> = (0..count)
+                .map(|i| PathBuf::from(format!("{}.txt", i)))
+                .collect();
+
+            gvim.open(&files, &opts).unwrap();
+            gvim.warnings
+        };
+
+        assert_eq!(warnings(16, &[]), [Warning::ManyTabs(16, 15)]);
+        assert!(warnings(15, &[]).is_empty());
+        assert!(warnings(16, &["--buffer"]).is_empty());
+        assert_eq!(
+            warnings(6, &["--warn-tabs", "5"]),
+            [Warning::ManyTabs(6, 5)]
+        );
+    }
+
+    #[test]
+    fn encoding_reaches_fresh_and_existing_instances() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--encoding", "shift_jis"].map(String::from)).unwrap();
+        let files = [PathBuf::from("a.txt")];
+
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls(),
+            [["-c", "argdo e ++enc=shift_jis", "-c", "first", "a.txt"]]
+        );
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls()[1..],
+            [
+                vec!["--servername", "GVIM", "--remote-tab", "a.txt"],
+                vec![
+                    "--servername",
+                    "GVIM",
+                    "--remote-send",
+                    "<C-\\><C-N>:exe 'tab drop ' . fnameescape('a.txt') | e ++enc=shift_jis<CR>"
+                ],
+            ]
+        );
+
+        for name in ["", "utf-8|!rm", "x".repeat(40).as_str()] {
+            assert!(Options::parse(&["--encoding".to_string(), name.to_string()]).is_err());
+        }
+    }
+
+    #[test]
+    fn searches_reach_fresh_and_existing_instances() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (mut opts, _) = Options::parse(&[]).unwrap();
+        opts.searches
+            .insert(PathBuf::from("a.html"), "<a href=\"/docs".to_string());
+        let files = [PathBuf::from("a.html")];
+
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(runner.calls(), [["+/<a href=\"\\/docs", "a.html"]]);
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls()[1..],
+            [
+                vec!["--servername", "GVIM", "--remote-tab", "a.html"],
+                vec![
+                    "--servername",
+                    "GVIM",
+                    "--remote-send",
+                    "<C-\\><C-N>/<lt>a href=\"\\/docs<CR>"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn search_suffixes_split_off_existing_files() {
+        let dir = temp_dir("search-suffix");
+        std::fs::write(dir.join("main.rs"), "fn main() {}").unwrap();
+        let file = dir.join("main.rs");
+
+        assert_eq!(
+            split_search(&dir.join("main.rs:/fn main"), Path::new("")),
+            Some((file.clone(), "fn main".to_string()))
+        );
+        assert_eq!(
+            split_search(&dir.join("main.rs:/a:/b"), Path::new("")),
+            Some((file.clone(), "a:/b".to_string()))
+        );
+        assert_eq!(split_search(&file, Path::new("")), None);
+        assert_eq!(
+            split_search(&dir.join("other.rs:/fn main"), Path::new("")),
+            None
+        );
+        assert_eq!(split_search(&dir.join("main.rs:/"), Path::new("")), None);
+    }
+
+    #[test]
+    fn commands_reach_fresh_and_existing_instances() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) =
+            Options::parse(&["--cmd", "set nu", "--cmd=map <F5> :make<CR>"].map(String::from))
+                .unwrap();
+        let files = [PathBuf::from("a.txt")];
+
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls(),
+            [["-c", "set nu", "-c", "map <F5> :make<CR>", "a.txt"]]
+        );
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls()[1..],
+            [
+                vec!["--servername", "GVIM", "--remote-tab", "a.txt"],
+                vec![
+                    "--servername",
+                    "GVIM",
+                    "--remote-send",
+                    "<C-\\><C-N>:set nu<CR>:map <lt>F5> :make<lt>CR><CR>"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn since_commit_takes_the_files_git_diff_names() {
+        let runner = RecordingRunner::replying(|args| CommandOutput {
+            success: args.contains(&"HEAD~3".to_string()),
+            stdout: "src/main.rs\nREADME.md\n".to_string(),
+            stderr: String::new(),
+        });
+        let root = Path::new("/repo");
+
+        assert_eq!(
+            changed_since(&mut runner.clone(), "HEAD~3", root).unwrap(),
+            [root.join("src/main.rs"), root.join("README.md")]
+        );
+        assert_eq!(
+            runner.calls(),
+            [["-C", "/repo", "diff", "--name-only", "HEAD~3", "--"]]
+        );
+
+        for reference in ["no-such-ref", "--output=x", ""] {
+            assert!(matches!(
+                changed_since(&mut runner.clone(), reference, root),
+                Err(AppError::InvalidOptionValue(_, value)) if value == reference
+            ));
+        }
+    }
+
+    #[test]
+    fn save_list_writes_the_files_for_a_later_argfile() {
+        let dir = temp_dir("save-list");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "").unwrap();
+        let list = dir.join("list.txt");
+
+        let mut app = App::new();
+        app.files = app.collect_files(vec![dir.clone()]).unwrap();
+        app.save_list(&list).unwrap();
+
+        let files = [dir.join("a.txt"), dir.join("sub/b.txt")];
+        assert_eq!(app.files, files);
+        assert_eq!(
+            std::fs::read_to_string(&list).unwrap(),
+            format!("{}\n{}\n", files[0].display(), files[1].display())
+        );
+
+        let items = vec![
+            PathBuf::from(format!("@{}", list.display())),
+            PathBuf::from("@x"),
+        ];
+        assert_eq!(
+            expand_list_files(items).unwrap(),
+            [files[0].clone(), files[1].clone(), PathBuf::from("@x")]
+        );
+    }
+
+    #[test]
+    fn diff_compares_the_files_in_fresh_and_existing_instances() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--diff".to_string()]).unwrap();
+        let files = [PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(runner.calls(), [["-d", "a.txt", "b.txt"]]);
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls()[1],
+            [
+                "--servername",
+                "GVIM",
+                "--remote-send",
+                "<C-\\><C-N>:exe 'tabedit ' . fnameescape('a.txt')<CR>\
+                 :exe 'vert diffsplit ' . fnameescape('b.txt')<CR>"
+            ]
+        );
+    }
+
+    #[test]
+    fn vsplit_all_splits_up_to_a_limit_and_tabs_the_rest() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--vsplit-all".to_string()]).unwrap();
+        let files = [PathBuf::from("a.txt"), PathBuf::from("b.txt")];
+
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(runner.calls(), [["-O", "a.txt", "b.txt"]]);
+        assert!(gvim.warnings.is_empty());
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&files, &opts).unwrap();
+        assert_eq!(
+            runner.calls()[1],
+            [
+                "--servername",
+                "GVIM",
+                "--remote-send",
+                "<C-\\><C-N>:exe 'tabedit ' . fnameescape('a.txt')<CR>\
+                 :exe 'rightbelow vsplit ' . fnameescape('b.txt')<CR>:1wincmd w<CR>"
+            ]
+        );
+
+        let many: Vec<_> = (0..MAX_SPLITS + 2)
+            .map(|i| PathBuf::from(format!("{}.txt", i)))
+            .collect();
+        gvim.open(&many, &opts).unwrap();
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[2][3].matches("vsplit").count(), MAX_SPLITS - 1);
+        assert_eq!(
+            calls[3],
+            ["--servername", "GVIM", "--remote-tab", "6.txt", "7.txt"]
+        );
+        assert!(matches!(
+            gvim.warnings[..],
+            [Warning::TooManySplits(8, MAX_SPLITS)]
+        ));
+    }
+
+    #[test]
+    fn presets_lay_out_the_files_in_fresh_and_existing_instances() {
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        app.config = Config::parse("[presets]\nreview = \"vsplit 2\"\n").unwrap();
+        (app.opts, _) = Options::parse(&["--preset", "review"].map(String::from)).unwrap();
+        app.resolve_layout(|_| None).unwrap();
+        app.files = ["a.txt", "b.txt", "it's.txt"].map(PathBuf::from).to_vec();
+
+        app.open().unwrap();
+        assert_eq!(
+            runner.calls(),
+            [[
+                "-c",
+                "exe 'edit ' . fnameescape('a.txt') | \
+                 exe 'rightbelow vsplit ' . fnameescape('b.txt') | 1wincmd w | \
+                 exe 'tabedit ' . fnameescape('it''s.txt') | \
+                 exe 'tabnext ' . (tabpagenr() - 1)"
+            ]]
+        );
+
+        app.gvim.detector = Box::new(MockDetector(Some(60)));
+        app.open().unwrap();
+        assert_eq!(
+            runner.calls()[1],
+            [
+                "--servername",
+                "GVIM",
+                "--remote-send",
+                "<C-\\><C-N>:exe 'tabedit ' . fnameescape('a.txt')<CR>\
+                 :exe 'rightbelow vsplit ' . fnameescape('b.txt')<CR>:1wincmd w<CR>\
+                 :exe 'tabedit ' . fnameescape('it''s.txt')<CR>\
+                 :exe 'tabnext ' . (tabpagenr() - 1)<CR>"
+            ]
+        );
+
+        (app.opts, _) = Options::parse(&["--preset", "grid"].map(String::from)).unwrap();
+        assert!(matches!(
+            app.resolve_layout(|_| None),
+            Err(AppError::UnknownPreset(name)) if name == "grid"
+        ));
+    }
+
+    #[test]
+    fn layout_defaults_to_gvi_layout_unless_given() {
+        let env =
+            |value: &'static str| move |key: &str| (key == "GVI_LAYOUT").then(|| value.to_string());
+        let layout = |args: &[&str], value: &'static str| {
+            let mut app = App::new();
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            (app.opts, _) = Options::parse(&args).unwrap();
+            app.resolve_layout(env(value)).unwrap();
+            (app.opts.layout, app.warnings)
+        };
+        let vsplit = |per_tab| Layout {
+            split: Split::Vertical,
+            per_tab,
+        };
+
+        assert_eq!(layout(&[], "vsplit"), (Some(vsplit(None)), vec![]));
+        assert_eq!(
+            layout(&["--layout", "vsplit 2"], "split 3"),
+            (Some(vsplit(Some(2))), vec![])
+        );
+        assert_eq!(layout(&["--layout", "tab"], "vsplit"), (None, vec![]));
+        assert_eq!(
+            layout(&[], "grid"),
+            (None, vec![Warning::InvalidLayout("grid".to_string())])
+        );
+        assert!(Options::parse(&["--layout", "grid"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn tab_per_arg_groups_the_files_by_argument() {
+        let dir = temp_dir("tab-per-arg");
+        // By size, the files of the two arguments take turns.
+        for (file, content) in [("src/a.rs", "1"), ("src/b.rs", "333"), ("docs/c.md", "22")] {
+            std::fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(file), content).unwrap();
+        }
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        (app.opts, _) =
+            Options::parse(&["--tab-per-arg", "--sort-by", "size"].map(String::from)).unwrap();
+        app.files = app
+            .collect_files(vec![dir.join("src"), dir.join("docs")])
+            .unwrap();
+        app.order_files();
+        app.open().unwrap();
+
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        assert_eq!(
+            runner.calls()[0][3],
+            format!(
+                "<C-\\><C-N>:exe 'tabedit ' . fnameescape('{}')<CR>\
+                 :exe 'rightbelow vsplit ' . fnameescape('{}')<CR>:1wincmd w<CR>\
+                 :exe 'tabedit ' . fnameescape('{}')<CR>\
+                 :exe 'tabnext ' . (tabpagenr() - 1)<CR>",
+                path("src/a.rs"),
+                path("src/b.rs"),
+                path("docs/c.md")
+            )
+        );
+    }
+
+    #[test]
+    fn remote_only_refuses_to_launch_an_instance() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--remote-only".to_string()]).unwrap();
+
+        assert!(matches!(
+            gvim.open(&[PathBuf::from("a.txt")], &opts),
+            Err(AppError::NoRunningServer(name)) if name == "GVIM"
+        ));
+        assert!(runner.calls().is_empty());
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+        assert_eq!(
+            runner.calls(),
+            [["--servername", "GVIM", "--remote-tab", "a.txt"]]
+        );
+    }
+
+    #[test]
+    fn count_only_prints_the_number_and_size_of_files() {
+        let dir = temp_dir("count-only");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "12345").unwrap();
+        std::fs::write(dir.join("sub/b.txt"), "123").unwrap();
+
+        let mut app = App::new();
+        app.files = app.collect_files(vec![dir]).unwrap();
+        let mut output = vec![];
+        app.print_count(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "2 files, 8 bytes\n");
+
+        app.files = app
+            .collect_files(vec![PathBuf::from("tests/test_asset/huge_file.txt")])
+            .unwrap();
+        let mut output = vec![];
+        app.print_count(&mut output).unwrap();
+        assert!(
+            String::from_utf8(output)
+                .unwrap()
+                .starts_with("1 files or more, ")
+        );
+    }
+
+    #[test]
+    fn count_dirs_tells_what_the_expansion_went_through() {
+        let dir = temp_dir("count-dirs");
+        std::fs::create_dir_all(dir.join("src/bin")).unwrap();
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        for file in [
+            "README.md",
+            "src/main.rs",
+            "src/notes.txt",
+            "src/bin/tool.rs",
+            "target/debug/gvi.rs",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--count-dirs", "--ext", "rs"].map(String::from)).unwrap();
+        assert!(app.opts.count_dirs);
+        app.files = app.collect_files(vec![dir]).unwrap();
+
+        // target is left out by default, README.md and notes.txt for their extensions.
+        assert_eq!(
+            app.counts,
+            ExpansionCounts {
+                dirs: 3,
+                files: 2,
+                skipped: 3,
+            }
+        );
+        let mut output = vec![];
+        app.print_dir_count(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "3 directories, 2 files, 3 skipped\n"
+        );
+    }
+
+    #[test]
+    fn gviignore_excludes_files_from_expansion() {
+        let dir = temp_dir("gviignore");
+        std::fs::create_dir_all(dir.join("src/generated")).unwrap();
+        std::fs::create_dir_all(dir.join("logs")).unwrap();
+        for file in [
+            "README.md",
+            "debug.log",
+            "logs/app.log",
+            "src/main.rs",
+            "src/main.rs.orig",
+            "src/generated/schema.rs",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        std::fs::write(dir.join(".gviignore"), "# noise\n*.log\n/logs/\n").unwrap();
+        std::fs::write(dir.join("src/.gviignore"), "*.orig\ngenerated/\n").unwrap();
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        let files = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs)
+            .unwrap()
+            .files;
+
+        assert_eq!(
+            files,
+            [".gviignore", "README.md", "src/.gviignore", "src/main.rs"].map(|f| dir.join(f))
+        );
+    }
+
+    #[test]
+    fn default_ignores_skip_build_directories() {
+        let dir = temp_dir("default_ignores");
+        std::fs::create_dir_all(dir.join("target/debug")).unwrap();
+        std::fs::write(dir.join("main.rs"), "").unwrap();
+        std::fs::write(dir.join("target/debug/build.log"), "").unwrap();
+        let expand = |args: &[&str]| {
+            let (opts, _) =
+                Options::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+                    .unwrap();
+            expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs)
+                .unwrap()
+                .files
+        };
+
+        assert_eq!(expand(&[]), [dir.join("main.rs")]);
+        assert_eq!(
+            expand(&["--no-default-ignores"]),
+            [dir.join("main.rs"), dir.join("target/debug/build.log")]
+        );
+    }
+
+    #[test]
+    fn into_tab_sends_tabedit_after_the_tab_page() {
+        let runner = RecordingRunner::replying(|args| {
+            stdout(if args.contains(&"--remote-expr".to_string()) {
+                "3\n"
+            } else {
+                ""
+            })
+        });
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (mut opts, _) = Options::parse(&["--into-tab", "2"].map(String::from)).unwrap();
+        opts.lines.insert(PathBuf::from("b.rs"), 12);
+
+        gvim.open(&["a.rs", "b.rs"].map(PathBuf::from), &opts)
+            .unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                vec!["--servername", "GVIM", "--remote-expr", "tabpagenr('$')"],
+                vec![
+                    "--servername",
+                    "GVIM",
+                    "--remote-send",
+                    "<C-\\><C-N>:tabnext 2<CR>\
+                     :exe 'tabedit ' . fnameescape('a.rs')<CR>\
+                     :exe 'tabedit +12 ' . fnameescape('b.rs')<CR>"
+                ],
+            ]
+        );
+
+        let (opts, _) = Options::parse(&["--into-tab", "4"].map(String::from)).unwrap();
+        assert!(matches!(
+            gvim.open(&[PathBuf::from("a.rs")], &opts),
+            Err(AppError::InvalidOptionValue(_, tab)) if tab == "4"
+        ));
+        assert!(Options::parse(&["--into-tab", "0"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn into_tab_goes_with_diff_vsplit_all_and_layouts() {
+        let send = |args: &[&str]| {
+            let runner = RecordingRunner::replying(|args| {
+                stdout(if args.contains(&"--remote-expr".to_string()) {
+                    "3\n"
+                } else {
+                    ""
+                })
+            });
+            let mut gvim = Gvim {
+                detector: Box::new(MockDetector(Some(60))),
+                runner: Box::new(runner.clone()),
+                clientserver: Some(true),
+                ..Gvim::default()
+            };
+            let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            args.extend(["--into-tab", "2"].map(String::from));
+            let (opts, _) = Options::parse(&args).unwrap();
+
+            gvim.open(&["a.rs", "b.rs"].map(PathBuf::from), &opts)
+                .unwrap();
+            runner.calls()
+        };
+
+        for args in [
+            &["--diff"][..],
+            &["--vsplit-all"],
+            &["--layout", "vsplit 2"],
+        ] {
+            let calls = send(args);
+            assert_eq!(
+                calls[0],
+                ["--servername", "GVIM", "--remote-expr", "tabpagenr('$')"]
+            );
+            assert!(
+                calls[1][3].starts_with("<C-\\><C-N>:tabnext 2<CR><C-\\><C-N>:exe 'tabedit "),
+                "{:?}: {}",
+                args,
+                calls[1][3]
+            );
+        }
+    }
+
+    #[test]
+    fn file_limit_counts_expanded_files_rather_than_arguments() {
+        let dir = temp_dir("file-limit");
+        let mut explicit = vec![];
+        for i in 0..25 {
+            let file = dir.join(format!("{:02}.txt", i));
+            std::fs::write(&file, "").unwrap();
+            explicit.push(file);
+        }
+        std::fs::create_dir_all(dir.join("sub/a")).unwrap();
+        std::fs::create_dir_all(dir.join("sub/b")).unwrap();
+        for i in 0..40 {
+            let sub = if i % 2 == 0 { "a" } else { "b" };
+            std::fs::write(dir.join(format!("sub/{}/{:02}.txt", sub, i)), "").unwrap();
+        }
+
+        // Many arguments, each a file.
+        let mut app = App::new();
+        assert!(count_arguments(&explicit) <= MAX_ARGS);
+        app.files = app.collect_files(explicit).unwrap();
+        assert_eq!(app.files.len(), 25);
+        assert!(!app.has_too_many_files());
+
+        // A single argument, expanding into many files.
+        let items = [dir.join("sub")];
+        assert!(count_arguments(&items) <= MAX_ARGS);
+        app.files = app.collect_files(items.to_vec()).unwrap();
+        assert_eq!(app.files.len(), 40);
+        assert!(app.has_too_many_files());
+        assert!(
+            app.too_many_files_message()
+                .starts_with("Nothing has been opened, because the arguments come to 40 files")
+        );
+
+        let absurd: Vec<PathBuf> = (0..=MAX_ARGS)
+            .map(|i| PathBuf::from(i.to_string()))
+            .collect();
+        assert!(count_arguments(&absurd) > MAX_ARGS);
+    }
+
+    #[test]
+    fn truncate_args_goes_on_with_the_first_arguments() {
+        let items: Vec<PathBuf> = (0..MAX_ARGS + 5)
+            .map(|i| PathBuf::from(i.to_string()))
+            .collect();
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--truncate-args".to_string()]).unwrap();
+
+        let kept = app.limit_arguments(items.clone()).unwrap();
+
+        assert_eq!(kept, items[..MAX_ARGS]);
+        assert_eq!(
+            app.take_warnings(),
+            [Warning::TruncatedArguments(MAX_ARGS + 5, MAX_ARGS)]
+        );
+    }
+
+    #[test]
+    fn until_size_opens_the_files_that_fit() {
+        let dir = temp_dir("until-size");
+        for (file, size) in [("a.txt", 4), ("b.txt", 4), ("c.txt", 4), ("d.txt", 1)] {
+            std::fs::write(dir.join(file), "x".repeat(size)).unwrap();
+        }
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--until-size", "10"].map(String::from)).unwrap();
+        app.files = app.collect_files(vec![dir.clone()]).unwrap();
+        app.take_until_size();
+
+        assert_eq!(app.files, [dir.join("a.txt"), dir.join("b.txt")]);
+        assert_eq!(app.size, Some(8));
+        assert_eq!(
+            app.take_warnings(),
+            [Warning::OverSizeBudget {
+                skipped: vec![dir.join("c.txt"), dir.join("d.txt")],
+                budget: 10,
+            }]
+        );
+    }
+
+    #[test]
+    fn files_over_the_per_file_limit_are_left_out() {
+        let dir = temp_dir("max-size-per-file");
+        std::fs::write(dir.join("big.log"), "1234567890").unwrap();
+        std::fs::write(dir.join("small.txt"), "12345").unwrap();
+        let expand = |path: PathBuf, args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            let (opts, _) = Options::parse(&args).unwrap();
+            expand_dir(path, 100, MAX_SIZE, &opts, &RealFs).unwrap()
+        };
+
+        let expansion = expand(dir.clone(), &["--max-size-per-file", "8"]);
+        assert_eq!(expansion.files, [dir.join("small.txt")]);
+        assert_eq!(expansion.size, 5);
+        assert_eq!(expansion.oversized, [(dir.join("big.log"), 10)]);
+
+        let expansion = expand(dir.join("big.log"), &["--max-size-per-file", "8"]);
+        assert!(expansion.files.is_empty());
+
+        let expansion = expand(
+            dir.join("big.log"),
+            &["--max-size-per-file", "8", "--keep-large-args"],
+        );
+        assert_eq!(expansion.files, [dir.join("big.log")]);
+        assert_eq!(expansion.oversized, [(dir.join("big.log"), 10)]);
+
+        let expansion = expand(dir.clone(), &["--keep-large-args"]);
+        assert_eq!(expansion.files.len(), 2);
+    }
+
+    #[test]
+    fn files_with_long_names_are_left_out() {
+        let dir = temp_dir("max-name-length");
+        let long = format!("{}.txt", "x".repeat(40));
+        std::fs::write(dir.join(&long), "").unwrap();
+        std::fs::write(dir.join("short.txt"), "").unwrap();
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--max-name-length", "20"].map(String::from)).unwrap();
+        let files = app.collect_files(vec![dir.clone()]).unwrap();
+        assert_eq!(files, [dir.join("short.txt")]);
+        assert_eq!(
+            app.take_warnings(),
+            [Warning::LongName(dir.join(&long), 20)]
+        );
+
+        // Unless given as an argument.
+        let files = app.collect_files(vec![dir.join(&long)]).unwrap();
+        assert_eq!(files, [dir.join(&long)]);
+        assert!(app.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn warnings_are_reported_together_by_kind() {
+        let dir = temp_dir("warnings");
+        std::fs::write(dir.join("app.1.log"), "").unwrap();
+        std::fs::write(dir.join("big.log"), "1234567890").unwrap();
+        let runner = RecordingRunner::replying(|_| stdout("-clientserver"));
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        (app.opts, _) =
+            Options::parse(&["--max-size-per-file".to_string(), "8".to_string()]).unwrap();
+
+        let items = expand_range_arguments(
+            vec![dir.join("app.{1..3}.log")],
+            MAX_ARGS,
+            &mut app.warnings,
+        );
+        app.files = app
+            .collect_files([items, vec![dir.clone()]].concat())
+            .unwrap();
+        app.open().unwrap();
+        app.open().unwrap();
+
+        assert_eq!(
+            app.take_warnings(),
+            [
+                Warning::NoClientserver(PathBuf::from("gvim")),
+                Warning::MissingRangeMembers {
+                    range: dir.join("app.{1..3}.log"),
+                    missing: 2,
+                    first: dir.join("app.2.log"),
+                },
+                Warning::Oversized {
+                    path: dir.join("big.log"),
+                    size: 10,
+                    limit: 8,
+                    opened: false,
+                },
+            ]
+        );
+        assert!(app.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn expansion_honors_custom_limits() {
+        let dir = temp_dir("limits");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        for file in ["1.txt", "2.txt", "3.txt", "a/4.txt", "a/b/5.txt"] {
+            std::fs::write(dir.join(file), "12").unwrap();
+        }
+        let (mut opts, _) = Options::parse(&[]).unwrap();
+        assert_eq!(opts.limits, LimitConfig::default());
+
+        opts.limits = LimitConfig {
+            max_files: 2,
+            ..LimitConfig::default()
+        };
+        let files = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs)
+            .unwrap()
+            .files;
+        assert_eq!(files, [dir.join("1.txt"), dir.join("2.txt")]);
+
+        (opts, _) = Options::parse(&["--max-depth", "2"].map(String::from)).unwrap();
+        let files = expand_dir(dir.clone(), 100, MAX_SIZE, &opts, &RealFs)
+            .unwrap()
+            .files;
+        assert_eq!(files.len(), 4);
+        assert!(!files.contains(&dir.join("a/b/5.txt")));
+
+        let mut app = App::new();
+        (app.opts, _) =
+            Options::parse(&["--max-total", "3", "--max-size", "5"].map(String::from)).unwrap();
+        assert!(matches!(
+            app.collect_files(vec![dir.clone()]),
+            Err(AppError::TooManyEntries { .. })
+        ));
+        app.opts.limits.max_total = 100;
+        app.files = app.collect_files(vec![dir.clone()]).unwrap();
+        assert_eq!(app.files.len(), 3);
+        assert!(app.has_large_size_of_files().unwrap());
+    }
+
+    fn background_calls() -> (Vec<Vec<String>>, Vec<Warning>) {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            editor: PathBuf::from("/Applications/MacVim.app/Contents/bin/gvim"),
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--background".to_string()]).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+
+        (runner.calls(), gvim.warnings)
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn background_opens_macvim_with_open_g() {
+        let (calls, _) = background_calls();
+
+        assert_eq!(
+            calls,
+            [[
+                "-g",
+                "-n",
+                "-a",
+                "/Applications/MacVim.app",
+                "--args",
+                "a.txt"
+            ]]
+        );
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn background_starts_gvim_minimized() {
+        let (calls, _) = background_calls();
+
+        assert_eq!(
+            calls,
+            [[
+                "/C",
+                "start",
+                "\"\"",
+                "/MIN",
+                "/Applications/MacVim.app/Contents/bin/gvim",
+                "a.txt"
+            ]]
+        );
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    #[test]
+    fn background_is_unsupported_elsewhere() {
+        let (calls, warnings) = background_calls();
+
+        assert_eq!(calls, [["a.txt"]]);
+        assert_eq!(warnings, [Warning::BackgroundUnsupported]);
+    }
+
+    #[test]
+    fn long_windows_paths_get_the_extended_length_form() {
+        let long = format!("{}\\notes.txt", "directory".repeat(30));
+
+        assert_eq!(
+            extended_length(&format!("C:\\{}", long)),
+            format!("\\\\?\\C:\\{}", long)
+        );
+        assert_eq!(
+            extended_length(&format!("\\\\server\\share\\{}", long)),
+            format!("\\\\?\\UNC\\server\\share\\{}", long)
+        );
+        assert_eq!(
+            extended_length("C:/Users/me/notes.txt"),
+            "C:/Users/me/notes.txt"
+        );
+        assert_eq!(
+            extended_length("\\\\server\\share\\notes.txt"),
+            "\\\\server\\share\\notes.txt"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn only_long_paths_are_rewritten_on_windows() {
+        let short = PathBuf::from("src\\main.rs");
+        let long = PathBuf::from("directory\\".repeat(30)).join("notes.txt");
+
+        assert_eq!(Gvim::default().target_path(&short), short);
+        assert_eq!(
+            Gvim::default().target_path(&long),
+            PathBuf::from(format!(
+                "\\\\?\\{}",
+                std::path::absolute(&long).unwrap().display()
+            ))
+        );
+    }
+
+    #[test]
+    fn wsl_detection_and_binary_selection() {
+        let distro = |key: &str| (key == "WSL_DISTRO_NAME").then(|| "Ubuntu".to_string());
+        assert!(is_wsl(distro, None));
+        assert!(is_wsl(
+            |_| None,
+            Some("5.15.153.1-microsoft-standard-WSL2\n")
+        ));
+        assert!(!is_wsl(|_| None, Some("6.8.0-45-generic\n")));
+
+        let (auto, _) = Options::parse(&[]).unwrap();
+        assert!(wants_windows_gvim(&auto, true, || false));
+        assert!(!wants_windows_gvim(&auto, true, || true));
+        assert!(!wants_windows_gvim(&auto, false, || false));
+
+        let (forced, _) = Options::parse(&["--wsl".to_string()]).unwrap();
+        assert!(wants_windows_gvim(&forced, false, || true));
+        let (disabled, _) = Options::parse(&["--no-wsl".to_string()]).unwrap();
+        assert!(!wants_windows_gvim(&disabled, true, || false));
+        let (explicit, _) =
+            Options::parse(&["--editor-path", "/usr/bin/gvim"].map(String::from)).unwrap();
+        assert!(!wants_windows_gvim(&explicit, true, || false));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn wsl_servers_are_found_without_a_process_to_see() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Stands in for gvim.exe, with a server registered but no gvim process of ours running.
+        let dir = temp_dir("wsl-detector");
+        let editor = dir.join("gvim.exe");
+        std::fs::write(&editor, "#!/bin/sh\necho GVIM\n").unwrap();
+        std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let server = wsl_detector(editor).find_server("GVIM").unwrap();
+
+        assert_eq!(server.name, "GVIM");
+        assert!(server.registered);
+    }
+
+    #[test]
+    fn wsl_paths_are_translated_for_the_windows_gvim() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            translate: Some(|path| {
+                PathBuf::from(format!(
+                    "\\\\wsl.localhost\\Ubuntu{}",
+                    path.display().to_string().replace('/', "\\")
+                ))
+            }),
+            ..Gvim::default()
+        };
+        let (mut opts, _) = Options::parse(&[]).unwrap();
+        opts.lines.insert(PathBuf::from("/home/me/b.rs"), 3);
+
+        gvim.open(
+            &["/home/me/a.rs", "/home/me/b.rs"].map(PathBuf::from),
+            &opts,
+        )
+        .unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                vec![
+                    "--servername",
+                    "GVIM",
+                    "--remote-tab",
+                    "\\\\wsl.localhost\\Ubuntu\\home\\me\\a.rs"
+                ],
+                vec![
+                    "--servername",
+                    "GVIM",
+                    "--remote-tab",
+                    "+3",
+                    "\\\\wsl.localhost\\Ubuntu\\home\\me\\b.rs"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_opens_nothing_if_an_argument_is_missing() {
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.args = [
+            "gvi",
+            "--strict",
+            "--editor-path",
+            "tests/test_asset/fake_gvim",
+            "README.md",
+            "tests/test_asset/no_such_file.txt",
+            "tests/test_asset/no_such_dir",
+        ]
+        .map(String::from)
+        .to_vec();
+        app.gvim.runner = Box::new(runner.clone());
+
+        let err = app.execute().unwrap_err();
+
+        assert!(runner.calls().is_empty());
+        assert_eq!(
+            err.to_string(),
+            "Nothing has been opened, because of these arguments:\n  \
+             tests/test_asset/no_such_file.txt: does not exist\n  \
+             tests/test_asset/no_such_dir: does not exist"
+        );
+        assert!(validate_arguments(&[PathBuf::from("README.md"), PathBuf::from("src")]).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn compressed_files_are_opened_from_a_decompressed_copy() {
+        let dir = temp_dir("decompress");
+        let broken = dir.join("broken.log.gz");
+        std::fs::write(&broken, [0x1f, 0x8b, 0x08, 0x00]).unwrap();
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        (app.opts, _) = Options::parse(&["--decompress", "--quiet"].map(String::from)).unwrap();
+        let fixture = PathBuf::from("tests/test_asset/app.log.gz");
+        app.opts.lines.insert(fixture.clone(), 2);
+        app.opts.sources.insert(fixture.clone(), 0);
+        app.opts
+            .filetypes
+            .insert(fixture.clone(), "messages".to_string());
+        app.files = vec![fixture, broken.clone()];
+        // Somebody else's file where the copy would go is left alone.
+        let taken = dir.join(format!("gvi-{}-0-app.log", std::process::id()));
+        std::fs::write(&taken, "taken").unwrap();
+
+        app.decompress_files(&dir);
+
+        let copy = dir.join(format!("gvi-{}-0.1-app.log", std::process::id()));
+        assert_eq!(app.files, [copy.clone(), broken.clone()]);
+        assert_eq!(std::fs::read_to_string(&taken).unwrap(), "taken");
+        assert_eq!(app.opts.sources.get(&copy), Some(&0));
+        assert_eq!(
+            app.opts.filetypes.get(&copy).map(String::as_str),
+            Some("messages")
+        );
+        assert_eq!(
+            std::fs::read_to_string(&copy).unwrap(),
+            "started\nlistening on :8080\n"
+        );
+        assert!(std::fs::metadata(&copy).unwrap().permissions().readonly());
+        assert!(
+            !dir.join(format!("gvi-{}-1-broken.log", std::process::id()))
+                .exists()
+        );
+        assert_eq!(app.warnings, [Warning::NotDecompressed(broken)]);
+
+        app.files.truncate(1);
+        app.open().unwrap();
+        assert_eq!(
+            runner.calls()[0],
+            ["+2".to_string(), copy.to_string_lossy().into_owned()]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn decompression_stops_at_the_size_limit() {
+        let dir = temp_dir("decompress-limit");
+        let fixture = PathBuf::from("tests/test_asset/app.log.gz");
+        let copy = dir.join(format!("gvi-{}-1-app.log", std::process::id()));
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(&["--decompress", "--quiet"].map(String::from)).unwrap();
+        app.files = vec![PathBuf::from("README.md"), fixture.clone()];
+        let readme = std::fs::metadata("README.md").unwrap().len();
+        app.opts.limits.max_size = readme + 10;
+
+        app.decompress_files(&dir);
+
+        // Cut short a byte over the limit, for check_limits to turn down.
+        assert_eq!(app.files, [PathBuf::from("README.md"), copy.clone()]);
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "started\nlis");
+        assert_eq!(app.size, Some(readme + 11));
+        assert!(app.has_large_size_of_files().unwrap());
+
+        app.remove_copies();
+        assert!(!copy.exists());
+
+        (app.opts, _) =
+            Options::parse(&["--decompress", "--quiet", "--until-size", "10"].map(String::from))
+                .unwrap();
+        app.files = vec![fixture.clone(), PathBuf::from("README.md")];
+
+        app.decompress_files(&dir);
+
+        assert!(app.files.is_empty());
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+        assert_eq!(
+            app.warnings.last(),
+            Some(&Warning::OverSizeBudget {
+                skipped: vec![fixture, PathBuf::from("README.md")],
+                budget: 10,
+            })
+        );
+    }
+
+    #[test]
+    fn copies_of_earlier_runs_are_removed() {
+        let dir = temp_dir("stale-copies");
+        let old = SystemTime::now() - STALE_COPY_AGE * 2;
+        for name in [
+            "gvi-1-0-app.log",
+            "gvi-1-2.1-app.log",
+            "gvi-stdin-1",
+            "notes.txt",
+        ] {
+            let file = std::fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(old).unwrap();
+        }
+        let recent = dir.join("gvi-2-0-app.log");
+        std::fs::write(&recent, "").unwrap();
+
+        remove_stale_copies(&dir);
+
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["gvi-2-0-app.log", "gvi-stdin-1", "notes.txt"]);
+    }
+
+    #[test]
+    fn stdin_content_is_opened_with_the_filetype() {
+        let dir = temp_dir("stdin");
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        (app.opts, _) = Options::parse(&["--ft", "yaml", "-"].map(String::from)).unwrap();
+
+        let stdin = app
+            .read_stdin_to_file(&mut "a: 1\n".as_bytes(), &dir)
+            .unwrap();
+        app.files = vec![stdin.clone(), PathBuf::from("b.txt")];
+        app.open().unwrap();
+
+        assert_eq!(std::fs::read_to_string(&stdin).unwrap(), "a: 1\n");
+        assert_eq!(
+            runner.calls()[0],
+            ["+setf yaml".to_string(), stdin.display().to_string()]
+        );
+
+        for filetype in ["", "yaml | !rm", "x".repeat(40).as_str()] {
+            assert!(Options::parse(&["--ft".to_string(), filetype.to_string()]).is_err());
+        }
+    }
+
+    #[test]
+    fn stdin_is_not_written_through_a_name_taken_already() {
+        let dir = temp_dir("stdin-taken");
+        let taken = dir.join(format!("gvi-stdin-{}", std::process::id()));
+        std::fs::write(&taken, "someone else's").unwrap();
+        let mut app = App::new();
+
+        let stdin = app
+            .read_stdin_to_file(&mut "mine".as_bytes(), &dir)
+            .unwrap();
+
+        assert_ne!(stdin, taken);
+        assert_eq!(std::fs::read_to_string(&taken).unwrap(), "someone else's");
+        assert_eq!(std::fs::read_to_string(&stdin).unwrap(), "mine");
+    }
+
+    #[test]
+    fn capability_cache_is_keyed_by_mtime() {
+        let cache = CapabilityCache {
+            file: temp_dir("capability-cache").join("capabilities"),
+        };
+        let editor = Path::new("/opt/vim/bin/gvim");
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let capabilities = Capabilities::from_version_output(
+            "VIM - Vi IMproved 9.1 (2024 Jan 02)\n+clientserver +clipboard",
+        );
+
+        assert_eq!(cache.load(editor, mtime), None);
+        cache.store(editor, mtime, &capabilities).unwrap();
+        assert_eq!(cache.load(editor, mtime), Some(capabilities));
+
+        assert_eq!(cache.load(editor, mtime + Duration::from_secs(1)), None);
+        assert_eq!(cache.load(Path::new("/usr/bin/gvim"), mtime), None);
+    }
+
+    #[test]
+    fn nvim_qt_gets_fresh_instances_without_a_version_probe() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            editor: PathBuf::from("/usr/bin/nvim-qt"),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+
+        assert!(!gvim.supports_clientserver());
+        assert!(runner.calls().is_empty());
+        assert_eq!(
+            gvim.warnings,
+            [Warning::NoClientserver(PathBuf::from("/usr/bin/nvim-qt"))]
+        );
+
+        assert!(
+            !Capabilities::from_version_output(
+                "NVIM v0.10.2
+Build type: Release"
+            )
+            .clientserver
+        );
+        assert_eq!(Capabilities::of_editor(Path::new("/usr/bin/mvim")), None);
+    }
+
+    #[test]
+    fn cached_capabilities_spare_the_version_probe() {
+        let file = temp_dir("capability-reuse").join("capabilities");
+        let gvim = |runner: &RecordingRunner| Gvim {
+            editor: PathBuf::from("tests/test_asset/fake_gvim"),
+            runner: Box::new(runner.clone()),
+            cache: Some(CapabilityCache { file: file.clone() }),
+            ..Gvim::default()
+        };
+
+        let runner = RecordingRunner::replying(|_| stdout("VIM 9.1\n-clientserver"));
+        assert!(!gvim(&runner).supports_clientserver());
+        assert_eq!(runner.calls(), [["--version"]]);
+
+        let runner = RecordingRunner::default();
+        assert!(!gvim(&runner).supports_clientserver());
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn focus_asks_the_server_to_come_forward() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&["--focus".to_string()]).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                vec!["--servername", "GVIM", "--remote-tab", "a.txt"],
+                vec!["--servername", "GVIM", "--remote-expr", "foreground()"],
+            ]
+        );
+    }
+
+    #[test]
+    fn record_session_has_the_server_write_the_session() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) =
+            Options::parse(&["--record-session", "/tmp/it's.vim"].map(String::from)).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+
+        assert_eq!(
+            runner.calls()[1],
+            [
+                "--servername",
+                "GVIM",
+                "--remote-send",
+                "<C-\\><C-N>:exe 'mksession! ' . fnameescape('/tmp/it''s.vim')<CR>"
+            ]
+        );
     }
 
     #[test]
-    fn success_to_open_large_file() {
+    fn open_session_launches_a_fresh_instance_restoring_it() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(PanickingDetector),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) =
+            Options::parse(&["--open-session", "review.vim"].map(String::from)).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                vec!["-S", "review.vim"],
+                vec!["--serverlist"],
+                vec!["--servername", "GVIM", "--remote-tab", "a.txt"],
+            ]
+        );
+    }
+
+    #[test]
+    fn close_quits_the_named_server() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(RegisteredAs("PROJECTX")),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+
+        gvim.close("projectx", false).unwrap();
+        gvim.close("projectx", true).unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                [
+                    "--servername",
+                    "PROJECTX",
+                    "--remote-send",
+                    "<C-\\><C-N>:qall<CR>"
+                ],
+                [
+                    "--servername",
+                    "PROJECTX",
+                    "--remote-send",
+                    "<C-\\><C-N>:qall!<CR>"
+                ],
+            ]
+        );
+
+        gvim.detector = Box::new(MockDetector(None));
+        assert!(matches!(
+            gvim.close("PROJECTY", false),
+            Err(AppError::ServerNotFound(name)) if name == "PROJECTY"
+        ));
+    }
+
+    #[test]
+    fn remote_expr_captures_the_result_and_maps_errors() {
+        static LOOKS: AtomicUsize = AtomicUsize::new(0);
+
+        let runner = RecordingRunner::replying(|args| match args[3].as_str() {
+            "tabpagenr('$')" => stdout("3\n"),
+            // Registers on the second look.
+            "v:servername" if LOOKS.fetch_add(1, Ordering::SeqCst) == 0 => CommandOutput {
+                success: false,
+                stdout: String::new(),
+                stderr: "E247: no registered server named \"GVIM\": Send expression failed.\n"
+                    .to_string(),
+            },
+            "v:servername" => stdout("GVIM\n"),
+            _ => CommandOutput {
+                success: false,
+                stdout: String::new(),
+                stderr: "E15: Invalid expression: \"bogus(\"\nE449: Invalid expression received\n"
+                    .to_string(),
+            },
+        });
+        let clock = MockClock::new();
+        let mut gvim = Gvim {
+            runner: Box::new(runner.clone()),
+            clock: Box::new(clock.clone()),
+            ..Gvim::default()
+        };
+
+        assert_eq!(gvim.remote_expr("tabpagenr('$')").unwrap(), "3");
+        assert_eq!(gvim.remote_expr("v:servername").unwrap(), "GVIM");
+        assert_eq!(clock.sleeps(), [Gvim::REGISTRATION_POLL_INTERVAL]);
+
+        let err = gvim.remote_expr("bogus(").unwrap_err();
+        assert!(matches!(err, AppError::RemoteExprFailed(..)));
+        assert_eq!(
+            err.to_string(),
+            "gvim server GVIM couldn't evaluate bogus(: E15: Invalid expression: \"bogus(\""
+        );
+        assert_eq!(runner.calls().len(), 4);
+    }
+
+    #[test]
+    fn rename_server_asks_the_server_to_register_again() {
+        let runner = RecordingRunner::replying(|_| CommandOutput {
+            success: false,
+            stdout: String::new(),
+            stderr: "E941: Already started a server\n".to_string(),
+        });
+        let mut gvim = Gvim {
+            detector: Box::new(RegisteredAs("OLD")),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let (opts, _) =
+            Options::parse(&["--rename-server", "old", "it's"].map(String::from)).unwrap();
+        let (old, new) = opts.rename_server.unwrap();
+
+        assert!(matches!(
+            gvim.rename_server(&old, &new),
+            Err(AppError::RenameRefused(old, new)) if old == "OLD" && new == "it's"
+        ));
+        assert_eq!(
+            runner.calls(),
+            [[
+                "--servername",
+                "OLD",
+                "--remote-expr",
+                "remote_startserver('it''s')"
+            ]]
+        );
+
+        gvim.detector = Box::new(MockDetector(None));
+        assert!(matches!(
+            gvim.rename_server("OLD", "NEW"),
+            Err(AppError::ServerNotFound(_))
+        ));
+        assert!(Options::parse(&["--rename-server", "OLD"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn keep_alive_launches_the_server_unless_it_is_running() {
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            server_name: "NOTES".to_string(),
+            ..Gvim::default()
+        };
+
+        gvim.keep_alive().unwrap();
+        assert_eq!(runner.calls(), [["--servername", "NOTES"]]);
+
+        gvim.detector = Box::new(MockDetector(Some(60)));
+        gvim.keep_alive().unwrap();
+        assert_eq!(runner.calls().len(), 1);
+    }
+
+    // The session id is the sixth field of /proc/<pid>/stat.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn detached_servers_get_a_session_of_their_own() {
+        let dir = temp_dir("detached");
+        let output = dir.join("session");
+        let gvim = Gvim {
+            editor: PathBuf::from("/bin/sh"),
+            ..Gvim::default()
+        };
+        let script = format!("cut -d' ' -f6 /proc/$$/stat > '{}'", output.display());
+
+        SystemRunner
+            .spawn(gvim.detached_command(["-c", &script]))
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let session = loop {
+            match std::fs::read_to_string(&output) {
+                Ok(session) if session.ends_with('\n') => break session,
+                _ if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                _ => panic!("the detached process never wrote its session id"),
+            }
+        };
+        let ours = unsafe { libc::getsid(0) };
+        assert_ne!(session.trim().parse::<i32>().unwrap(), ours);
+    }
+
+    #[test]
+    fn fresh_instance_is_waited_for_until_it_registers() {
+        static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+        let runner = RecordingRunner::replying(|args| {
+            // Registers on the third look.
+            if args == ["--serverlist"] && POLLS.fetch_add(1, Ordering::SeqCst) >= 2 {
+                stdout("GVIM\n")
+            } else {
+                stdout("")
+            }
+        });
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            arg_budget: 6,
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&[]).unwrap();
+        let started = std::time::Instant::now();
+
+        gvim.open(&[PathBuf::from("a.txt"), PathBuf::from("b.txt")], &opts)
+            .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(
+            runner.calls(),
+            [
+                vec!["a.txt"],
+                vec!["--serverlist"],
+                vec!["--serverlist"],
+                vec!["--serverlist"],
+                vec!["--servername", "GVIM", "--remote-tab", "b.txt"],
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_arguments_can_be_taken_from_the_server() {
+        let runner = RecordingRunner::replying(|args| {
+            stdout(match args.last().map(String::as_str) {
+                Some("getcwd()") => "/srv/project\n",
+                _ => "+clientserver",
+            })
+        });
         let mut app = App::new();
-        app.files = vec![PathBuf::from("tests/test_asset/huge_file_but_ok.txt")];
-        assert!(!app.has_large_size_of_files());
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let args = ["--relative-to-server", "+3", "src/main.rs", "/etc/hosts"].map(String::from);
+        let (opts, items) = Options::parse(&args).unwrap();
+        app.opts = opts;
+        let items: Vec<PathBuf> = items.iter().map(PathBuf::from).collect();
+
+        let resolved = app.resolve_against_server(items.clone()).unwrap();
+
+        let main = Path::new("/srv/project").join("src/main.rs");
+        assert_eq!(resolved, [main.clone(), PathBuf::from("/etc/hosts")]);
+        assert_eq!(app.opts.lines.get(&main), Some(&3));
+        assert_eq!(
+            &runner.calls().last().unwrap()[..3],
+            ["--servername", "GVIM", "--remote-expr"]
+        );
+
+        // Without a server, they stay relative to where we are.
+        app.gvim.detector = Box::new(MockDetector(None));
+        assert_eq!(app.resolve_against_server(items.clone()).unwrap(), items);
+    }
+
+    #[test]
+    fn search_suffixes_are_split_off_files_of_the_server() {
+        let dir = temp_dir("server-search");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/app.rs"), "fn run() {}").unwrap();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            // The server works in the directory made above, which is not ours.
+            runner: Box::new(RecordingRunner::replying(|args| {
+                stdout(&match args.last().map(String::as_str) {
+                    Some("getcwd()") => format!(
+                        "{}\n",
+                        std::env::temp_dir()
+                            .join(format!("gvi-test-{}-server-search", std::process::id()))
+                            .display()
+                    ),
+                    _ => "+clientserver".to_string(),
+                })
+            })),
+            ..Gvim::default()
+        };
+        (app.opts, _) = Options::parse(&["--relative-to-server".to_string()]).unwrap();
+
+        let resolved = app
+            .resolve_against_server(vec![PathBuf::from("lib/app.rs:/fn run")])
+            .unwrap();
+
+        let file = dir.join("lib/app.rs");
+        assert_eq!(resolved, [dir.join("lib/app.rs")]);
+        assert_eq!(
+            app.opts.searches.get(&file).map(String::as_str),
+            Some("fn run")
+        );
+    }
+
+    #[test]
+    fn loaded_buffers_are_switched_to_rather_than_opened_again() {
+        let runner = RecordingRunner::replying(|args| {
+            stdout(if args.contains(&"--remote-expr".to_string()) {
+                "/src/main.rs\n/README.md\n"
+            } else {
+                "+clientserver"
+            })
+        });
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let args = ["--no-tab-for-existing-buffer"].map(String::from);
+        let (opts, _) = Options::parse(&args).unwrap();
+
+        let files = [PathBuf::from("/src/main.rs"), PathBuf::from("/src/lib.rs")];
+        gvim.open(&files, &opts).unwrap();
+
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0][..3], ["--servername", "GVIM", "--remote-expr"]);
+        assert_eq!(
+            calls[1],
+            ["--servername", "GVIM", "--remote-tab", "/src/lib.rs"]
+        );
+        assert_eq!(
+            calls[2],
+            [
+                "--servername",
+                "GVIM",
+                "--remote-send",
+                "<C-\\><C-N>:exe 'tab drop ' . fnameescape('/src/main.rs')<CR>",
+            ]
+        );
+        assert_eq!(gvim.opened, 2);
+    }
+
+    #[test]
+    fn list_opened_prints_the_buffers_of_the_server() {
+        let runner = RecordingRunner::replying(|args| {
+            stdout(if args.contains(&"--remote-expr".to_string()) {
+                "/src/main.rs\n/README.md\n"
+            } else {
+                "+clientserver"
+            })
+        });
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        let (mut output, mut status) = (vec![], vec![]);
+
+        app.list_opened(&mut output, &mut status).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "/src/main.rs\n/README.md\n"
+        );
+        assert!(status.is_empty());
+        assert_eq!(
+            &runner.calls().last().unwrap()[..3],
+            ["--servername", "GVIM", "--remote-expr"]
+        );
+
+        app.gvim.detector = Box::new(MockDetector(None));
+        let (mut output, mut status) = (vec![], vec![]);
+
+        app.list_opened(&mut output, &mut status).unwrap();
+
+        assert!(output.is_empty());
+        assert_eq!(
+            String::from_utf8(status).unwrap(),
+            "Nothing is open in GVIM.\n"
+        );
+    }
+
+    #[test]
+    fn reverse_leaves_the_first_argument_in_the_active_tab() {
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            ..Gvim::default()
+        };
+        (app.opts, _) = Options::parse(&["--reverse".to_string()]).unwrap();
+        app.files = ["a.rs", "b.rs", "c.rs"].map(PathBuf::from).to_vec();
+
+        app.order_files();
+        app.open().unwrap();
+
+        assert_eq!(
+            runner.calls()[1..],
+            [[
+                "--servername",
+                "GVIM",
+                "--remote-tab",
+                "c.rs",
+                "b.rs",
+                "a.rs"
+            ]]
+        );
+    }
+
+    #[test]
+    fn preserve_arg_order_keeps_directories_in_place() {
+        let dir = temp_dir("arg-order");
+        std::fs::create_dir(dir.join("d")).unwrap();
+        for file in ["z.txt", "a.txt", "d/b.txt", "d/a.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let args = ["z.txt", "d", "a.txt"].map(|arg| dir.join(arg)).to_vec();
+
+        let mut app = App::new();
+        (app.opts, _) = Options::parse(
+            &["--sort-by", "name", "--reverse", "--preserve-arg-order"].map(String::from),
+        )
+        .unwrap();
+        app.files = app.collect_files(args).unwrap();
+        app.order_files();
+
+        assert_eq!(
+            app.files,
+            ["z.txt", "d/a.txt", "d/b.txt", "a.txt"].map(|file| dir.join(file))
+        );
+    }
+
+    #[test]
+    fn interrupt_stops_the_remaining_batches() {
+        static STOP: AtomicBool = AtomicBool::new(false);
+
+        // Hits Ctrl-C right after the first invocation.
+        struct InterruptingRunner(RecordingRunner);
+
+        impl Runner for InterruptingRunner {
+            fn spawn(&mut self, command: Command) -> std::io::Result<()> {
+                self.0.spawn(command)?;
+                STOP.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+
+            fn output(&mut self, command: Command) -> std::io::Result<CommandOutput> {
+                self.0.output(command)
+            }
+        }
+
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(InterruptingRunner(runner.clone())),
+            arg_budget: 16,
+            clientserver: Some(true),
+            interrupted: &STOP,
+            ..Gvim::default()
+        };
+        let files = ["a.txt", "b.txt", "c.txt", "d.txt"].map(PathBuf::from);
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        let result = gvim.open(&files, &opts);
+
+        assert!(matches!(result, Err(AppError::Interrupted(2))));
+        assert_eq!(
+            runner.calls(),
+            [["--servername", "GVIM", "--remote-tab", "a.txt", "b.txt"]]
+        );
+    }
+
+    #[test]
+    fn opener_receives_the_expanded_files() {
+        // Remembers what it was asked to open.
+        struct MockOpener(Arc<Mutex<Vec<PathBuf>>>);
+
+        impl Opener for MockOpener {
+            fn open(&mut self, files: &[PathBuf], _: &Options) -> Result<(), AppError> {
+                self.0.lock().unwrap().extend_from_slice(files);
+                Ok(())
+            }
+        }
+
+        let dir = temp_dir("opener");
+        std::fs::create_dir(dir.join("src")).unwrap();
+        for file in ["README.md", "src/lib.rs", "src/main.rs"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let received = Arc::new(Mutex::new(vec![]));
+
+        let mut app = App::new();
+        app.opener = Some(Box::new(MockOpener(received.clone())));
+        app.files = app
+            .collect_files(vec![dir.join("README.md"), dir.join("src")])
+            .unwrap();
+        app.open().unwrap();
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            ["README.md", "src/lib.rs", "src/main.rs"].map(|file| dir.join(file))
+        );
+    }
+
+    #[test]
+    fn code_opener_reuses_the_window() {
+        let runner = RecordingRunner::default();
+        let mut opener = CodeOpener {
+            editor: PathBuf::from("code"),
+            runner: Box::new(runner.clone()),
+        };
+        let (mut opts, _) = Options::parse(&["--opener", "code"].map(String::from)).unwrap();
+        opts.lines.insert(PathBuf::from("b.rs"), 7);
+
+        opener
+            .open(&["a.rs", "b.rs"].map(PathBuf::from), &opts)
+            .unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [["--reuse-window", "a.rs", "--goto", "b.rs:7"]]
+        );
+    }
+
+    #[test]
+    fn files_are_routed_by_extension() {
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        app.config = Config::parse("[routes]\nmd = \"NOTES\"\n.rs = \"CODE\" # code\n").unwrap();
+        app.files = ["notes.md", "main.rs", "build.sh"]
+            .map(PathBuf::from)
+            .to_vec();
+
+        app.open().unwrap();
+
+        assert_eq!(
+            runner.calls(),
+            [
+                vec!["--servername", "NOTES", "--remote-tab", "notes.md"],
+                vec!["--servername", "CODE", "--remote-tab", "main.rs"],
+                vec!["--servername", "GVIM", "--remote-tab", "build.sh"],
+            ]
+        );
+    }
+
+    #[test]
+    fn the_plan_is_made_before_anything_is_opened() {
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(Some(60))),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        app.config = Config::parse("[routes]\nmd = \"NOTES\"\n").unwrap();
+        app.files = ["notes.md", "main.rs"].map(PathBuf::from).to_vec();
+        app.opts.lines.insert(PathBuf::from("main.rs"), 7);
+        app.opts
+            .searches
+            .insert(PathBuf::from("notes.md"), "TODO".to_string());
+
+        let plan = app.plan();
+
+        assert_eq!(
+            plan,
+            OpenPlan {
+                targets: vec![
+                    PlannedTarget {
+                        server: "NOTES".to_string(),
+                        instance: Instance::Existing(60),
+                        explanation: vec![
+                            "1 file(s) for server NOTES".to_string(),
+                            "Found server NOTES, up for 60s".to_string(),
+                            "Reusing it through remote commands".to_string(),
+                        ],
+                        files: vec![PlannedFile {
+                            path: PathBuf::from("notes.md"),
+                            line: None,
+                            search: Some("TODO".to_string()),
+                            filetype: None,
+                        }],
+                    },
+                    PlannedTarget {
+                        server: "GVIM".to_string(),
+                        instance: Instance::Existing(60),
+                        explanation: vec![
+                            "1 file(s) for server GVIM".to_string(),
+                            "Found server GVIM, up for 60s".to_string(),
+                            "Reusing it through remote commands".to_string(),
+                        ],
+                        files: vec![PlannedFile {
+                            path: PathBuf::from("main.rs"),
+                            line: Some(7),
+                            search: None,
+                            filetype: None,
+                        }],
+                    },
+                ],
+            }
+        );
+        assert!(runner.calls().is_empty());
+        assert_eq!(app.gvim.server_name, "GVIM");
+
+        app.carry_out(plan).unwrap();
+        assert_eq!(runner.calls().len(), 3);
+    }
+
+    #[test]
+    fn background_detection_is_joined_before_the_first_open() {
+        let threads = Arc::new(Mutex::new(vec![]));
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(ThreadDetector(threads.clone())),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        app.files = ["a.rs", "b.rs"].map(PathBuf::from).to_vec();
+
+        app.gvim.detect_in_background();
+        let plan = app.plan();
+
+        // Found on the other thread, and that answer is what the files went by.
+        assert_eq!(threads.lock().unwrap().len(), 1);
+        assert_ne!(threads.lock().unwrap()[0], std::thread::current().id());
+        assert_eq!(plan.targets[0].instance, Instance::Existing(60));
+        assert!(app.gvim.pending.is_none());
+        assert!(runner.calls().is_empty());
+
+        app.carry_out(plan).unwrap();
+        assert_eq!(
+            runner.calls()[0][..3],
+            ["--servername", "GVIM", "--remote-tab"]
+        );
+
+        // Another server is looked for by the detector it gave back, here.
+        assert!(app.gvim.find_server("NOTES").is_some());
+        assert_eq!(threads.lock().unwrap()[1], std::thread::current().id());
+    }
+
+    #[test]
+    fn explain_tells_each_target_of_the_plan() {
+        let runner = RecordingRunner::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            explanation: Some(vec![]),
+            dry: true,
+            ..Gvim::default()
+        };
+        app.config = Config::parse("[routes]\nmd = \"NOTES\"\n").unwrap();
+        app.files = ["notes.md", "main.rs"].map(PathBuf::from).to_vec();
+
+        let plan = app.plan();
+        assert_eq!(app.gvim.explanation, Some(vec![]));
+        app.carry_out(plan).unwrap();
+
+        assert!(runner.calls().is_empty());
+        assert_eq!(
+            app.gvim.explanation.unwrap(),
+            [
+                "1 file(s) for server NOTES",
+                "No running gvim for server NOTES",
+                "Launching a fresh instance as NOTES",
+                "Would run: gvim --servername NOTES notes.md",
+                "1 file(s) for server GVIM",
+                "No running gvim for server GVIM",
+                "Launching a fresh instance as GVIM",
+                "Would run: gvim main.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn max_concurrent_spawns_waits_for_earlier_instances_to_register() {
+        // Instances start up until gvi asks for the servers, when the oldest one registers.
+        // The servers starting up and registered, and the most ever starting up at once.
+        type Servers = (Vec<String>, Vec<String>, usize);
+
+        #[derive(Clone, Default)]
+        struct StartingUp(Arc<Mutex<Servers>>);
+
+        impl Runner for StartingUp {
+            fn spawn(&mut self, command: Command) -> std::io::Result<()> {
+                let args: Vec<String> = command
+                    .get_args()
+                    .map(|a| a.to_string_lossy().into_owned())
+                    .collect();
+                let name = match args.iter().position(|arg| arg == "--servername") {
+                    Some(i) => args[i + 1].clone(),
+                    None => "GVIM".to_string(),
+                };
+
+                let (starting, _, most) = &mut *self.0.lock().unwrap();
+                starting.push(name);
+                *most = (*most).max(starting.len());
+                Ok(())
+            }
+
+            fn output(&mut self, command: Command) -> std::io::Result<CommandOutput> {
+                let (starting, registered, _) = &mut *self.0.lock().unwrap();
+
+                if command.get_args().any(|arg| arg == "--serverlist") && !starting.is_empty() {
+                    registered.push(starting.remove(0));
+                }
+                Ok(stdout(&format!("+clientserver\n{}", registered.join("\n"))))
+            }
+        }
+
+        let runner = StartingUp::default();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        app.config =
+            Config::parse("[routes]\nmd = \"A\"\nrs = \"B\"\nsh = \"C\"\ntxt = \"D\"").unwrap();
+        (app.opts, _) =
+            Options::parse(&["--max-concurrent-spawns", "2"].map(String::from)).unwrap();
+        app.files = ["a.md", "b.rs", "c.sh", "d.txt", "e.toml"]
+            .map(PathBuf::from)
+            .to_vec();
+
+        app.open().unwrap();
+
+        let (starting, registered, most) = &*runner.0.lock().unwrap();
+        assert_eq!(*most, 2);
+        assert_eq!(starting.len() + registered.len(), 5);
+        assert!(Options::parse(&["--max-concurrent-spawns", "0"].map(String::from)).is_err());
+    }
+
+    #[test]
+    fn startup_waits_go_by_the_clock() {
+        let clock = MockClock::new();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(1))),
+            runner: Box::new(RecordingRunner::default()),
+            clock: Box::new(clock.clone()),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        // An instance up for a second gets the two it may still need to register.
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+        assert_eq!(clock.sleeps(), [Duration::from_secs(2)]);
+
+        // A fresh instance that never registers gets polled for three seconds.
+        let clock = MockClock::new();
+        let runner = RecordingRunner::replying(|_| stdout(""));
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(runner.clone()),
+            clock: Box::new(clock.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+
+        gvim.wait_until_registered();
+
+        let sleeps = clock.sleeps();
+        assert_eq!(sleeps.len(), 60);
+        assert_eq!(sleeps.iter().sum::<Duration>(), Duration::from_secs(3));
+        assert_eq!(runner.calls().len(), 61);
+    }
+
+    #[test]
+    fn a_registered_server_is_not_waited_for() {
+        // Up for a second, but already in --serverlist.
+        struct JustRegistered;
+
+        impl InstanceDetector for JustRegistered {
+            fn find_server(&self, name: &str) -> Option<detect::ServerInfo> {
+                Some(detect::ServerInfo {
+                    name: name.to_string(),
+                    running_time: Some(1),
+                    registered: true,
+                })
+            }
+        }
+
+        let clock = MockClock::new();
+        let runner = RecordingRunner::default();
+        let mut gvim = Gvim {
+            detector: Box::new(JustRegistered),
+            runner: Box::new(runner.clone()),
+            clock: Box::new(clock.clone()),
+            clientserver: Some(true),
+            ..Gvim::default()
+        };
+        let (opts, _) = Options::parse(&[]).unwrap();
+
+        gvim.open(&[PathBuf::from("a.txt")], &opts).unwrap();
+        assert_eq!(clock.sleeps().iter().sum::<Duration>(), Duration::ZERO);
+        assert_eq!(
+            runner.calls()[0][..3],
+            ["--servername", "GVIM", "--remote-tab"]
+        );
+    }
+
+    #[test]
+    fn explain_tells_the_decisions_without_opening() {
+        let explain = |detector: MockDetector| {
+            let runner = RecordingRunner::default();
+            let clock = MockClock::new();
+            let mut gvim = Gvim {
+                detector: Box::new(detector),
+                runner: Box::new(runner.clone()),
+                clock: Box::new(clock.clone()),
+                clientserver: Some(true),
+                explanation: Some(vec![]),
+                dry: true,
+                ..Gvim::default()
+            };
+            let (opts, items) =
+                Options::parse(&["--explain", "a file.rs"].map(String::from)).unwrap();
+            assert!(opts.explain);
+
+            gvim.open(&items.iter().map(PathBuf::from).collect::<Vec<_>>(), &opts)
+                .unwrap();
+            assert!(runner.calls().is_empty());
+            assert!(clock.sleeps().is_empty());
+            gvim.explanation.unwrap()
+        };
+
+        assert_eq!(
+            explain(MockDetector(Some(1))),
+            [
+                "1 file(s) for server GVIM",
+                "Found server GVIM, up for 1s",
+                "Reusing it through remote commands",
+                "Waiting 2000 ms for it to take remote commands",
+                "Would run: gvim --servername GVIM --remote-tab \"a file.rs\"",
+            ]
+        );
+        assert_eq!(
+            explain(MockDetector(None)),
+            [
+                "1 file(s) for server GVIM",
+                "No running gvim for server GVIM",
+                "Launching a fresh instance as GVIM",
+                "Would run: gvim \"a file.rs\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn open_delay_spaces_out_the_remote_invocations() {
+        let open = |args: &[&str]| {
+            let clock = MockClock::new();
+            let runner = RecordingRunner::default();
+            let mut gvim = Gvim {
+                detector: Box::new(MockDetector(Some(60))),
+                runner: Box::new(runner.clone()),
+                clock: Box::new(clock.clone()),
+                clientserver: Some(true),
+                ..Gvim::default()
+            };
+            let (mut opts, _) =
+                Options::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+                    .unwrap();
+            opts.lines.insert(PathBuf::from("b.rs"), 3);
+            opts.lines.insert(PathBuf::from("c.rs"), 9);
+
+            let files = ["a.rs", "b.rs", "c.rs"].map(PathBuf::from);
+            gvim.open(&files, &opts).unwrap();
+            assert_eq!(runner.calls().len(), 3);
+            clock.sleeps()
+        };
+
+        assert_eq!(
+            open(&["--open-delay-ms", "50"]),
+            [
+                Duration::ZERO,
+                Duration::from_millis(50),
+                Duration::from_millis(50)
+            ]
+        );
+        assert_eq!(open(&["--open-delay-ms", "0"]), [Duration::ZERO]);
+        assert_eq!(open(&[]), [Duration::ZERO]);
+    }
+
+    #[test]
+    fn startup_timeout_comes_from_the_flag_then_the_environment() {
+        let env = |value: &'static str| {
+            move |key: &str| (key == "GVI_STARTUP_TIMEOUT_MS").then(|| value.to_string())
+        };
+        let (plain, _) = Options::parse(&[]).unwrap();
+        let (flag, _) = Options::parse(&["--startup-timeout", "500"].map(String::from)).unwrap();
+        let mut warnings = vec![];
+
+        let timeout = Gvim::resolve_startup_timeout(&plain, env("1500"), &mut warnings);
+        assert_eq!(timeout, Duration::from_millis(1500));
+        let timeout = Gvim::resolve_startup_timeout(&flag, env("1500"), &mut warnings);
+        assert_eq!(timeout, Duration::from_millis(500));
+        assert!(warnings.is_empty());
+
+        let timeout = Gvim::resolve_startup_timeout(&plain, env("-1"), &mut warnings);
+        assert_eq!(timeout, Gvim::PROCESS_RUNNING_TIME_THRESHOLD);
+        assert_eq!(warnings, [Warning::InvalidStartupTimeout("-1".to_string())]);
+
+        // An instance up for a second gets what is left of the timeout.
+        let clock = MockClock::new();
+        let mut gvim = Gvim {
+            detector: Box::new(MockDetector(Some(1))),
+            runner: Box::new(RecordingRunner::default()),
+            clock: Box::new(clock.clone()),
+            startup_timeout: Gvim::resolve_startup_timeout(&plain, env("1500"), &mut warnings),
+            ..Gvim::default()
+        };
+        gvim.open(&[PathBuf::from("a.txt")], &plain).unwrap();
+        assert_eq!(clock.sleeps(), [Duration::from_millis(500)]);
+    }
+
+    #[test]
+    fn profile_has_a_line_per_phase() {
+        let dir = temp_dir("profile");
+        std::fs::write(dir.join("config.toml"), "sort-by = \"name\"\n").unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        let mut app = App::new();
+        app.gvim = Gvim {
+            detector: Box::new(MockDetector(None)),
+            runner: Box::new(RecordingRunner::default()),
+            ..Gvim::default()
+        };
+
+        app.load_config(dir.join("config.toml"), false).unwrap();
+        app.files = app.collect_files(vec![dir.join("a.txt")]).unwrap();
+        app.check_limits().unwrap();
+        app.open().unwrap();
+
+        let mut output = vec![];
+        app.print_profile(&mut output);
+        let output = String::from_utf8(output).unwrap();
+        let phases: Vec<&str> = output
+            .lines()
+            .map(|line| {
+                assert!(line.starts_with("profile: ") && line.ends_with(" ms"));
+                line["profile: ".len()..line.len() - 3]
+                    .trim_end_matches(|c: char| c.is_ascii_digit() || c == '.' || c == ' ')
+            })
+            .collect();
+        assert_eq!(
+            phases,
+            ["config", "expansion", "size check", "detection", "spawning"]
+        );
+    }
+
+    #[test]
+    fn the_selected_profile_wins_over_the_top_level_settings() {
+        let mut app = App::new();
+        app.config = Config::parse(
+            "sort-by = \"name\"\nserver-name = \"HOME\"\n\
+             [profiles.work]\nserver-name = \"WORK\"\nstrict = true\n\
+             [profiles.\"personal\"]\nsort-by = \"mtime\"\n",
+        )
+        .unwrap();
+        let opts = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            Options::parse(&app.config_args(&args).unwrap()).unwrap().0
+        };
+
+        let work = opts(&["--profile-name", "work"]);
+        assert_eq!(work.server_name.as_deref(), Some("WORK"));
+        assert_eq!(work.sort_by, Some(SortKey::Name));
+        assert!(work.strict);
+
+        let personal = opts(&["--profile-name=personal", "--server-name", "X"]);
+        assert_eq!(personal.server_name.as_deref(), Some("X"));
+        assert_eq!(personal.sort_by, Some(SortKey::Mtime));
+        assert!(!personal.strict);
+
+        assert_eq!(opts(&[]).server_name.as_deref(), Some("HOME"));
+        assert!(matches!(
+            app.config_args(&["--profile-name", "play"].map(String::from)),
+            Err(AppError::UnknownProfile(name)) if name == "play"
+        ));
+    }
+
+    #[test]
+    fn gvi_config_names_the_config_file() {
+        let dir = temp_dir("gvi_config");
+        let fixture = dir.join("fixture.toml");
+        std::fs::write(&fixture, "sort-by = \"mtime\"\n[routes]\nmd = \"NOTES\"\n").unwrap();
+        let env = |vars: Vec<(&'static str, String)>| {
+            move |key: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| value.clone())
+            }
+        };
+
+        let (path, required) = Config::location(env(vec![
+            ("GVI_CONFIG", fixture.to_string_lossy().into_owned()),
+            ("XDG_CONFIG_HOME", "/xdg".to_string()),
+        ]))
+        .unwrap();
+        assert_eq!((&path, required), (&fixture, true));
+
+        let mut app = App::new();
+        app.load_config(path, required).unwrap();
+        assert_eq!(app.config.route(Path::new("todo.md")), Some("NOTES"));
+        let (opts, _) = Options::parse(&app.config_args(&[]).unwrap()).unwrap();
+        assert_eq!(opts.sort_by, Some(SortKey::Mtime));
+
+        let missing = dir.join("missing.toml");
+        assert!(matches!(
+            app.load_config(missing.clone(), true),
+            Err(AppError::MissingConfig(path)) if path == missing
+        ));
+        assert!(app.load_config(missing, false).is_ok());
+
+        // Without it, the usual place, under $XDG_CONFIG_HOME if set.
+        #[cfg(not(target_os = "windows"))]
+        {
+            let home = ("HOME", "/home/me".to_string());
+            assert_eq!(
+                Config::location(env(vec![("GVI_CONFIG", String::new()), home.clone()])),
+                Some((PathBuf::from("/home/me/.config/gvi/config.toml"), false))
+            );
+            assert_eq!(
+                Config::location(env(vec![("XDG_CONFIG_HOME", "/xdg".to_string()), home])),
+                Some((PathBuf::from("/xdg/gvi/config.toml"), false))
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(Config::parse("[routes]\nmd = \"NOTES").is_err());
+        assert!(Config::parse("[colors]\nmd = \"red\"").is_err());
+        assert!(Config::parse("just some words").is_err());
+        assert!(Config::parse("[presets]\nreview = \"grid 2\"").is_err());
+        assert!(Config::parse("[presets]\nreview = \"vsplit 0\"").is_err());
+    }
+
+    #[test]
+    fn dumped_config_names_the_source_of_each_value() {
+        let mut app = App::new();
+        app.args = ["gvi", "--verbose", "--confirm", "3"]
+            .map(String::from)
+            .to_vec();
+        app.config =
+            Config::parse("confirm = 10\nstrict = true\n[routes]\nmd = \"NOTES\"").unwrap();
+        let args = [app.config.args(), app.args[1..].to_vec()].concat();
+        (app.opts, _) = Options::parse(&args).unwrap();
+
+        let env = |key: &str| (key == "GVI_PROJECT").then(|| "web".to_string());
+        let mut output = vec![];
+        app.dump_config(env, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("confirm = 3 # flag\n"));
+        assert!(output.contains("strict = true # config\n"));
+        assert!(output.contains("server-name = \"web\" # env\n"));
+        assert!(output.contains("fast = false # default\n"));
+        assert!(output.contains("# editor-path is unset # default\n"));
+        assert!(output.ends_with("[routes]\nmd = \"NOTES\"\n"));
     }
 }
@@ -0,0 +1,34 @@
+use serde::Deserialize;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+// A list of files written by some tool, as JSON:
+//
+//     [
+//         { "path": "src/main.rs", "line": 42 },
+//         { "path": "README.md" }
+//     ]
+//
+// Other keys are allowed and ignored.
+#[derive(Deserialize)]
+struct Entry {
+    path: PathBuf,
+    // Lines count from 1.
+    line: Option<NonZeroUsize>,
+}
+
+pub fn parse(content: &str) -> Result<Vec<(PathBuf, Option<usize>)>, String> {
+    let entries: Vec<Entry> = serde_json::from_str(content).map_err(|e| e.to_string())?;
+
+    if entries
+        .iter()
+        .any(|entry| entry.path.as_os_str().is_empty())
+    {
+        return Err("an entry has an empty \"path\"".to_string());
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.path, entry.line.map(NonZeroUsize::get)))
+        .collect())
+}